@@ -1,12 +1,210 @@
-use clap::Parser;
+use crate::inputs::InputOrder;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
+use transaction_manager_lib::checkpoints::AsOf;
+use transaction_manager_lib::fees::FeeRate;
+use transaction_manager_lib::generator::WorkloadGenerator;
+use transaction_manager_lib::policy::NegativeBalancePolicy;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    /// Input CSV containing transactions
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Without a subcommand, the inputs are applied and the balances printed
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Apply the inputs and print the balances as they were at an earlier point
+    Balances {
+        #[command(flatten)]
+        as_of: AsOfArgs,
+        /// Copy the state at least this many transactions apart, so that fewer have to be
+        /// replayed. Copies are taken further apart as the run goes on, so that together
+        /// they take at most about twice the memory of the balances and history, on top of
+        /// a copy of every transaction
+        #[arg(long, default_value_t = 10_000)]
+        checkpoint_interval: usize,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Check balances against expected ones, e.g. those of the core ledger, listing every
+    /// client missing from either side or with funds or a lock which don't match
+    #[command(mut_arg("inputs", |arg| arg.required(false).required_unless_present("actual")))]
+    Reconcile {
+        /// CSV of the expected balances, with `client`, `available`, `held`, `total`,
+        /// `locked` and optionally `currency` columns
+        expected: PathBuf,
+        /// CSV of the balances to check, e.g. the output of an earlier run. Without it,
+        /// the inputs are applied and the balances they result in are checked
+        #[arg(long, value_name = "PATH", conflicts_with = "inputs")]
+        actual: Option<PathBuf>,
+        /// Largest difference in any of the funds which still counts as a match
+        #[arg(long, default_value_t = 0.0)]
+        tolerance: f64,
+        /// How to report the summary and discrepancies
+        #[arg(long, value_enum, default_value_t = ReportFormat::Csv)]
+        format: ReportFormat,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Print a random but reproducible CSV of transactions, for load testing and fuzzing
+    Generate(GenerateArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct GenerateArgs {
+    /// The same seed always generates the same transactions
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Number of clients the transactions are spread between [default: 100]
+    #[arg(long)]
+    pub clients: Option<u16>,
+    /// Number of rows, including invalid ones [default: 1000]
+    #[arg(long)]
+    pub transactions: Option<usize>,
+    /// Share of the rows which dispute an earlier deposit [default: 0.02]
+    #[arg(long)]
+    pub dispute_rate: Option<f64>,
+    /// Share of the rows which resolve an open dispute [default: 0.01]
+    #[arg(long)]
+    pub resolve_rate: Option<f64>,
+    /// Share of the rows which charge back an open dispute [default: 0.005]
+    #[arg(long)]
+    pub chargeback_rate: Option<f64>,
+    /// Share of the rows which can't be parsed or are always rejected [default: 0]
+    #[arg(long)]
+    pub invalid_rate: Option<f64>,
+    /// Write the balances the transactions should result in to this file
+    #[arg(long, value_name = "PATH")]
+    pub expected: Option<PathBuf>,
+}
+
+impl GenerateArgs {
+    pub fn generator(&self) -> WorkloadGenerator {
+        let mut generator = WorkloadGenerator::new(self.seed);
+        if let Some(clients) = self.clients {
+            generator = generator.with_clients(clients);
+        }
+        if let Some(transactions) = self.transactions {
+            generator = generator.with_transactions(transactions);
+        }
+        if let Some(rate) = self.dispute_rate {
+            generator = generator.with_dispute_rate(rate);
+        }
+        if let Some(rate) = self.resolve_rate {
+            generator = generator.with_resolve_rate(rate);
+        }
+        if let Some(rate) = self.chargeback_rate {
+            generator = generator.with_chargeback_rate(rate);
+        }
+        if let Some(rate) = self.invalid_rate {
+            generator = generator.with_invalid_rate(rate);
+        }
+        generator
+    }
+}
+
+/// How `reconcile` reports what it found
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    /// A row per discrepancy on stdout, and the summary on stderr
+    Csv,
+    /// The summary and discrepancies together on stdout
+    Json,
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false, conflicts_with = "dry_run")]
+pub struct AsOfArgs {
+    /// Number of transactions to have been recorded, whether or not they were applied,
+    /// counting from the first row of the first input. 0 is before any of them
+    #[arg(long, value_name = "SEQ")]
+    pub as_of: Option<usize>,
+    /// Seconds since the Unix epoch, by which every transaction up to then has been
+    /// recorded. Transactions without a timestamp happened at the latest one before them
+    #[arg(long, value_name = "TIMESTAMP")]
+    pub as_of_time: Option<u64>,
+}
+
+impl AsOfArgs {
+    pub fn as_of(&self) -> AsOf {
+        match (self.as_of, self.as_of_time) {
+            (_, Some(timestamp)) => AsOf::Timestamp(timestamp),
+            (sequence, None) => AsOf::Sequence(sequence.unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Input CSVs containing transactions, or glob patterns matching them, e.g.
+    /// `'shards/*.csv'`. All of them are applied to the same accounts
     // TODO: Could make this an argument that takes a flag
-    pub input: PathBuf,
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+    /// Order to process the inputs in
+    #[arg(long, value_enum, default_value_t = InputOrder::Name)]
+    pub order: InputOrder,
+    /// Don't process any more inputs after one which had rows that couldn't be parsed
+    /// or applied
+    #[arg(long)]
+    pub stop_on_error: bool,
+    /// File recording every processed row and what became of it, so that rows already
+    /// processed by a previous run are skipped
+    #[arg(long)]
+    pub idempotency_index: Option<PathBuf>,
+    /// Reject disputes raised more than this many seconds after the deposit
+    #[arg(long)]
+    pub max_dispute_age: Option<u64>,
+    /// Automatically resolve disputes still open this many seconds after being raised
+    #[arg(long)]
+    pub max_dispute_duration: Option<u64>,
+    /// What to do with disputes of funds already withdrawn: `allow` the available funds
+    /// to go negative, `reject` the dispute, or `cap` the hold at what's available
+    #[arg(long, default_value = "allow")]
+    pub negative_balance_policy: NegativeBalancePolicy,
+    /// Fee levied on every deposit, either flat (e.g. `0.5`) or a percentage (e.g. `1.5%`)
+    #[arg(long)]
+    pub deposit_fee: Option<FeeRate>,
+    /// Fee levied on every withdrawal, either flat (e.g. `0.5`) or a percentage (e.g. `1.5%`)
+    #[arg(long)]
+    pub withdrawal_fee: Option<FeeRate>,
+    /// CSV of `from,to,rate` exchange rates used for exchange transactions
+    #[arg(long)]
+    pub exchange_rates: Option<PathBuf>,
+    /// CSV of `client,limit` overdraft limits, applied before any transactions
+    #[arg(long)]
+    pub overdraft_limits: Option<PathBuf>,
+    /// TOML or JSON file of rules checked against every transaction before it's applied
+    #[arg(long)]
+    pub rules: Option<PathBuf>,
+    /// File of client ids, one per line, whose transactions are all rejected, e.g. a
+    /// sanctions list
+    #[arg(long)]
+    pub blocked_clients: Option<PathBuf>,
+    /// Reject deposits, withdrawals, transfers, fees, interest and exchanges of more than
+    /// this amount
+    #[arg(long)]
+    pub max_amount: Option<f64>,
+    /// Print statistics of the run to stderr, or write them as JSON to the given file
+    #[arg(long, value_name = "PATH", num_args = 0..=1, default_missing_value = "-")]
+    pub stats: Option<PathBuf>,
+    /// Write metrics of the run to the given file, in the Prometheus text exposition format
+    #[arg(long, value_name = "PATH")]
+    pub metrics: Option<PathBuf>,
+    /// Serve metrics over HTTP on the given address, e.g. `127.0.0.1:9898`, for as long
+    /// as the run lasts
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_addr: Option<String>,
+    /// Rather than printing the balances, print how applying this CSV of corrections on
+    /// top of the inputs would change them, without applying it
+    #[arg(long, value_name = "PATH")]
+    pub dry_run: Option<PathBuf>,
     // TODO: In the future we could add an output flag
     //   which would let us choose the output file
 }
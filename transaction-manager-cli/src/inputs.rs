@@ -0,0 +1,393 @@
+use clap::ValueEnum;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Order input files are processed in
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum InputOrder {
+    /// By path, e.g. `shard-01.csv` before `shard-02.csv`
+    #[default]
+    Name,
+    /// By when each file was last modified, oldest first
+    Mtime,
+    /// By the `# sequence: N` line at the top of each file
+    Sequence,
+}
+
+/// What happened to the rows of a single input file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    pub rows: usize,
+    pub applied: usize,
+    pub failed: usize,
+    pub unparsable: usize,
+    pub already_applied: usize,
+    pub held: usize,
+}
+
+impl FileSummary {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            ..Self::default()
+        }
+    }
+
+    /// Whether any row failed to be parsed or applied
+    pub fn has_errors(&self) -> bool {
+        self.failed > 0 || self.unparsable > 0
+    }
+}
+
+impl fmt::Display for FileSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} rows, {} applied, {} failed, {} unparsable, {} already applied, {} held",
+            self.path.display(),
+            self.rows,
+            self.applied,
+            self.failed,
+            self.unparsable,
+            self.already_applied,
+            self.held
+        )
+    }
+}
+
+/// Expands any glob patterns among `inputs` and puts the files in `order`. Files named
+/// more than once are only processed once.
+pub fn resolve(inputs: &[String], order: InputOrder) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+
+    for input in inputs {
+        if !is_pattern(input) {
+            paths.push(PathBuf::from(input));
+            continue;
+        }
+
+        let matched = glob::glob(input)?.collect::<Result<Vec<_>, _>>()?;
+        if matched.is_empty() {
+            return Err(format!("No input files match {input}").into());
+        }
+        paths.extend(matched);
+    }
+
+    paths.sort();
+    paths.dedup();
+
+    // Both sorts are stable, so files with the same key stay in name order
+    match order {
+        InputOrder::Name => {}
+        InputOrder::Mtime => {
+            let mut modified = Vec::with_capacity(paths.len());
+            for path in paths {
+                modified.push((path.metadata()?.modified()?, path));
+            }
+            modified.sort_by_key(|(modified, _)| *modified);
+            paths = modified.into_iter().map(|(_, path)| path).collect();
+        }
+        InputOrder::Sequence => {
+            let mut sequenced = Vec::with_capacity(paths.len());
+            for path in paths {
+                let Some(sequence) = sequence_number(&path)? else {
+                    return Err(format!("No sequence header in {}", path.display()).into());
+                };
+                sequenced.push((sequence, path));
+            }
+            sequenced.sort_by_key(|(sequence, _)| *sequence);
+            paths = sequenced.into_iter().map(|(_, path)| path).collect();
+        }
+    }
+
+    Ok(paths)
+}
+
+fn is_pattern(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Opens an input for reading, decompressing it on the fly if it's gzip or zstd
+/// compressed, going by either its extension or its first few bytes
+pub fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let extension = path.extension().and_then(|extension| extension.to_str());
+
+    // Only peeks, so nothing is lost if the input isn't compressed
+    let start = reader.fill_buf()?;
+
+    if extension == Some("gz") || start.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else if extension == Some("zst") || start.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(zstd::Decoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Opens an input like `open`, but past its `# sequence: N` line if it starts with one,
+/// so that only the CSV is left
+pub fn open_csv(path: &Path) -> io::Result<Box<dyn Read>> {
+    skip_sequence_line(BufReader::new(open(path)?))
+}
+
+// Decoders may return less than a line at a time, so the whole first line is read before
+// it's looked at, and put back in front of the rest if it isn't a `# sequence: N` line
+fn skip_sequence_line<R: BufRead + 'static>(mut reader: R) -> io::Result<Box<dyn Read>> {
+    let mut first_line = Vec::new();
+    reader.read_until(b'\n', &mut first_line)?;
+
+    let is_sequence =
+        std::str::from_utf8(&first_line).is_ok_and(|line| parse_sequence(line).is_some());
+    if is_sequence {
+        return Ok(Box::new(reader));
+    }
+
+    Ok(Box::new(io::Cursor::new(first_line).chain(reader)))
+}
+
+/// Reads the `# sequence: N` line, if the file starts with one
+pub fn sequence_number(path: &Path) -> io::Result<Option<u64>> {
+    let mut first_line = String::new();
+    BufReader::new(open(path)?).read_line(&mut first_line)?;
+
+    let Some(sequence) = parse_sequence(&first_line) else {
+        return Ok(None);
+    };
+
+    sequence.parse().map(Some).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid sequence header in {}: {e}", path.display()),
+        )
+    })
+}
+
+// The number of a `# sequence: N` line, unparsed
+fn parse_sequence(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix('#')
+        .and_then(|comment| comment.trim().strip_prefix("sequence:"))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    // An empty directory of its own for each test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("inputs-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn pattern(dir: &Path, name: &str) -> String {
+        dir.join(name).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_resolve_globs_once_each() {
+        let dir = scratch_dir("globs");
+        for name in ["shard-02.csv", "shard-01.csv", "notes.txt"] {
+            fs::write(dir.join(name), "type,client,tx,amount\n").unwrap();
+        }
+
+        let paths = resolve(
+            &[
+                pattern(&dir, "shard-*.csv"),
+                pattern(&dir, "shard-01.csv"),
+                pattern(&dir, "shard-0[1].csv"),
+            ],
+            InputOrder::Name,
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            vec![dir.join("shard-01.csv"), dir.join("shard-02.csv")]
+        );
+
+        // Paths which aren't patterns are left for opening them to fail
+        let paths = resolve(&[pattern(&dir, "missing.csv")], InputOrder::Name).unwrap();
+        assert_eq!(paths, vec![dir.join("missing.csv")]);
+        assert!(resolve(&[pattern(&dir, "*.gz")], InputOrder::Name).is_err());
+    }
+
+    #[test]
+    fn test_resolve_by_mtime() {
+        let dir = scratch_dir("mtime");
+        let now = SystemTime::now();
+        for (name, age) in [("a.csv", 10), ("b.csv", 30), ("c.csv", 20)] {
+            let file = File::create(dir.join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+
+        let paths = resolve(&[pattern(&dir, "*.csv")], InputOrder::Mtime).unwrap();
+        assert_eq!(
+            paths,
+            vec![dir.join("b.csv"), dir.join("c.csv"), dir.join("a.csv")]
+        );
+    }
+
+    #[test]
+    fn test_resolve_by_sequence() {
+        let dir = scratch_dir("sequence");
+        fs::write(dir.join("a.csv"), "# sequence: 3\ntype,client,tx,amount\n").unwrap();
+        fs::write(dir.join("b.csv"), "#sequence:1\ntype,client,tx,amount\n").unwrap();
+        fs::write(
+            dir.join("c.csv"),
+            "  # sequence: 2  \ntype,client,tx,amount\n",
+        )
+        .unwrap();
+        fs::write(dir.join("d.csv"), "# sequence: 2\ntype,client,tx,amount\n").unwrap();
+
+        let paths = resolve(&[pattern(&dir, "*.csv")], InputOrder::Sequence).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                dir.join("b.csv"),
+                dir.join("c.csv"),
+                dir.join("d.csv"),
+                dir.join("a.csv")
+            ]
+        );
+
+        fs::write(dir.join("e.csv"), "type,client,tx,amount\n").unwrap();
+        assert!(resolve(&[pattern(&dir, "*.csv")], InputOrder::Sequence).is_err());
+        assert_eq!(sequence_number(&dir.join("e.csv")).unwrap(), None);
+
+        fs::write(dir.join("e.csv"), "# sequence: two\n").unwrap();
+        let err = sequence_number(&dir.join("e.csv")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_open_csv_only_skips_sequence_header() {
+        let dir = scratch_dir("header");
+        let rows = "type,client,tx,amount\ndeposit,1,1,1.0\n# not a comment,1,2,1.0\n";
+
+        for (name, contents) in [
+            ("sequenced.csv", format!("# sequence: 7\n{rows}")),
+            ("plain.csv", rows.to_string()),
+        ] {
+            fs::write(dir.join(name), contents).unwrap();
+
+            let mut read = String::new();
+            open_csv(&dir.join(name))
+                .unwrap()
+                .read_to_string(&mut read)
+                .unwrap();
+            assert_eq!(read, rows);
+        }
+
+        // Any other comment is left for the CSV reader to reject
+        fs::write(dir.join("comment.csv"), format!("# shard 7\n{rows}")).unwrap();
+        let mut read = String::new();
+        open_csv(&dir.join("comment.csv"))
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert!(read.starts_with("# shard 7\n"));
+    }
+
+    #[test]
+    fn test_open_compressed() {
+        let dir = scratch_dir("compressed");
+        let rows = "type,client,tx,amount\ndeposit,1,1,1.0\n".repeat(1000);
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut gzip, rows.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(rows.as_bytes(), 0).unwrap();
+        assert!(gzip.starts_with(GZIP_MAGIC));
+        assert!(zstd.starts_with(ZSTD_MAGIC));
+
+        // Without an extension they're only told apart by their first few bytes
+        for (name, contents) in [
+            ("shard.csv.gz", &gzip),
+            ("shard.csv.zst", &zstd),
+            ("gzip-shard", &gzip),
+            ("zstd-shard", &zstd),
+            ("plain-shard", &rows.as_bytes().to_vec()),
+        ] {
+            fs::write(dir.join(name), contents).unwrap();
+
+            let mut read = String::new();
+            open(&dir.join(name))
+                .unwrap()
+                .read_to_string(&mut read)
+                .unwrap();
+            assert_eq!(read, rows, "{name}");
+        }
+
+        // Concatenated gzip members are read as one
+        fs::write(dir.join("members.gz"), [gzip.clone(), gzip].concat()).unwrap();
+        let mut read = String::new();
+        open(&dir.join("members.gz"))
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, rows.repeat(2));
+
+        // As is the sequence header of a compressed input
+        let sequenced = format!("# sequence: 4\n{rows}");
+        let zstd = zstd::encode_all(sequenced.as_bytes(), 0).unwrap();
+        fs::write(dir.join("sequenced.zst"), zstd).unwrap();
+        assert_eq!(
+            sequence_number(&dir.join("sequenced.zst")).unwrap(),
+            Some(4)
+        );
+        let mut read = String::new();
+        open_csv(&dir.join("sequenced.zst"))
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, rows);
+    }
+
+    #[test]
+    fn test_sequence_line_skipped_across_short_reads() {
+        let rows = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+        for contents in [format!("# sequence: 4\n{rows}"), rows.to_string()] {
+            // Only ever a byte at a time
+            let reader = BufReader::with_capacity(1, io::Cursor::new(contents.into_bytes()));
+            let mut read = String::new();
+            skip_sequence_line(reader)
+                .unwrap()
+                .read_to_string(&mut read)
+                .unwrap();
+            assert_eq!(read, rows);
+        }
+    }
+
+    #[test]
+    fn test_file_summary() {
+        let mut summary = FileSummary::new(Path::new("shard-01.csv"));
+        summary.rows = 4;
+        summary.applied = 2;
+        summary.already_applied = 1;
+        summary.held = 1;
+        assert!(!summary.has_errors());
+        assert_eq!(
+            summary.to_string(),
+            "shard-01.csv: 4 rows, 2 applied, 0 failed, 0 unparsable, 1 already applied, 1 held"
+        );
+
+        summary.unparsable = 1;
+        assert!(summary.has_errors());
+        summary.unparsable = 0;
+        summary.failed = 1;
+        assert!(summary.has_errors());
+    }
+}
@@ -1,42 +1,409 @@
 use clap::Parser;
 use csv::ReaderBuilder;
-use log::*;
 use std::error::Error;
-use std::fs::File;
-use transaction_manager_lib::transaction_manager::TransactionManager;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, error, info, info_span, trace, warn};
+use tracing_subscriber::EnvFilter;
+use transaction_manager_lib::balance::ClientBalanceRegistry;
+use transaction_manager_lib::checkpoints::AsOf;
+use transaction_manager_lib::currency::StaticRates;
+use transaction_manager_lib::fees::FeeSchedule;
+use transaction_manager_lib::idempotency::IdempotencyIndex;
+use transaction_manager_lib::metrics::{self, Metrics};
+use transaction_manager_lib::policy::DisputePolicy;
+use transaction_manager_lib::reconciliation::Reconciliation;
+use transaction_manager_lib::rules::RulesEngine;
+use transaction_manager_lib::transaction_manager::{TransactionManager, TransactionManagerError};
 use transaction_manager_lib::transactions::Transaction;
+use transaction_manager_lib::validators::{BlockedClients, MaxAmount};
 
 mod cli;
+mod inputs;
+
+use cli::{Command, GenerateArgs, ReportFormat, RunArgs};
+use inputs::FileSummary;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
+    // Logs go to stderr, leaving stdout for the balances. Filtered with `RUST_LOG`,
+    // e.g. `RUST_LOG=transaction_manager_lib=debug`
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
 
     let cli = cli::Cli::parse();
-    trace!("cli: {:?}", cli.input);
-    let file = File::open(cli.input)?;
-    // Configuring to make sure we trim all whitespace from headers and fields
-    let mut rdr = ReaderBuilder::new().trim(csv::Trim::All).from_reader(file);
-
-    let mut transaction_manager = TransactionManager::new();
+    trace!(?cli);
 
-    for result in rdr.deserialize::<Transaction>() {
-        debug!("result: {result:?}");
+    match &cli.command {
+        None => run(&cli.run, None),
+        Some(Command::Balances {
+            as_of,
+            checkpoint_interval,
+            run: args,
+        }) => run(args, Some((as_of.as_of(), *checkpoint_interval))),
+        Some(Command::Reconcile {
+            expected,
+            actual,
+            tolerance,
+            format,
+            run: args,
+        }) => reconcile(expected, actual.as_deref(), *tolerance, *format, args),
+        Some(Command::Generate(args)) => generate(args),
+    }
+}
 
-        let Ok(transaction) = result else {
-            error!("Unable to parse this transaction");
-            continue;
-        };
+// Applies the inputs and prints the balances, either once they've all been applied or,
+// with checkpoints taken at the given interval, as of an earlier point
+fn run(cli: &RunArgs, as_of: Option<(AsOf, usize)>) -> Result<(), Box<dyn Error>> {
+    let transaction_manager = apply_inputs(cli, as_of.map(|(_, interval)| interval))?;
 
-        debug!("transaction: {transaction:?}");
+    if let Some(corrections_path) = &cli.dry_run {
+        let corrections = read_transactions(corrections_path)?;
+        let simulation = transaction_manager.simulate(&corrections);
 
-        if let Err(e) = transaction_manager.record_transaction(&transaction) {
-            warn!("Transaction failed to be inserted: transaction: {transaction:?} err: {e:?}");
+        if !simulation.rejections.is_empty() {
+            eprintln!(
+                "{} of {} corrections would be rejected:",
+                simulation.rejections.len(),
+                corrections.len()
+            );
+            for rejection in &simulation.rejections {
+                eprintln!("  {:?}: {}", corrections[rejection.index], rejection.error);
+            }
         }
+
+        print!("{}", simulation.to_csv());
+        return Ok(());
     }
 
-    let client_balance_registry = transaction_manager.retrieve_client_balances();
+    let client_balance_registry = match as_of {
+        Some((as_of, _)) => transaction_manager
+            .balances_as_of(as_of)
+            .ok_or_else(|| format!("The inputs end before {as_of}"))?,
+        None => transaction_manager.retrieve_client_balances(),
+    };
 
     println!("{}", client_balance_registry.to_csv());
 
     Ok(())
 }
+
+// Checks the balances in the file at `actual`, or failing that those the inputs result
+// in, against those in the file at `expected`, failing if they don't match up
+fn reconcile(
+    expected: &Path,
+    actual: Option<&Path>,
+    tolerance: f64,
+    format: ReportFormat,
+    cli: &RunArgs,
+) -> Result<(), Box<dyn Error>> {
+    let expected = ClientBalanceRegistry::from_file(expected)?;
+    let actual = match actual {
+        Some(actual) => ClientBalanceRegistry::from_file(actual)?,
+        None => apply_inputs(cli, None)?.retrieve_client_balances(),
+    };
+
+    let reconciliation = Reconciliation::new(&expected, &actual, tolerance);
+
+    match format {
+        ReportFormat::Csv => {
+            eprintln!("{}", reconciliation.summary);
+            print!("{}", reconciliation.to_csv());
+        }
+        ReportFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&reconciliation)?);
+        }
+    }
+
+    if !reconciliation.is_reconciled() {
+        return Err(format!("{} discrepancies found", reconciliation.discrepancies.len()).into());
+    }
+
+    Ok(())
+}
+
+// Prints a generated CSV of transactions, optionally writing the balances they should
+// result in to a file
+fn generate(args: &GenerateArgs) -> Result<(), Box<dyn Error>> {
+    let workload = args.generator().generate();
+    debug!(
+        transactions = workload.transactions.len(),
+        "Generated workload"
+    );
+
+    print!("{}", workload.csv);
+
+    if let Some(expected_path) = &args.expected {
+        fs::write(expected_path, workload.expected_csv())?;
+    }
+
+    Ok(())
+}
+
+// Applies every input, reporting on them as it goes, optionally taking checkpoints at
+// the given interval
+fn apply_inputs(
+    cli: &RunArgs,
+    checkpoint_interval: Option<usize>,
+) -> Result<TransactionManager, Box<dyn Error>> {
+    let input_paths = inputs::resolve(&cli.inputs, cli.order)?;
+    debug!(?input_paths);
+
+    let mut transaction_manager = TransactionManager::new()
+        .with_dispute_policy(DisputePolicy::new(
+            cli.max_dispute_age,
+            cli.max_dispute_duration,
+        ))
+        .with_negative_balance_policy(cli.negative_balance_policy)
+        .with_fee_schedule(FeeSchedule::new(cli.deposit_fee, cli.withdrawal_fee));
+    if let Some(checkpoint_interval) = checkpoint_interval {
+        transaction_manager = transaction_manager.with_checkpoints(checkpoint_interval);
+    }
+    if let Some(rates_path) = &cli.exchange_rates {
+        transaction_manager =
+            transaction_manager.with_rate_provider(StaticRates::from_file(rates_path)?);
+    }
+    if let Some(rules_path) = &cli.rules {
+        let rules = load_rules(rules_path)?;
+        debug!(rules = ?rules.rules());
+        transaction_manager = transaction_manager.with_rules(rules);
+    }
+    if let Some(blocked_path) = &cli.blocked_clients {
+        transaction_manager =
+            transaction_manager.with_validator(BlockedClients::from_file(blocked_path)?);
+    }
+    if let Some(max_amount) = cli.max_amount {
+        transaction_manager = transaction_manager.with_validator(MaxAmount::new(max_amount));
+    }
+    let metrics = Arc::new(Metrics::new());
+    if cli.metrics.is_some() || cli.metrics_addr.is_some() {
+        transaction_manager = transaction_manager.with_metrics(metrics.clone());
+    }
+    if let Some(metrics_addr) = &cli.metrics_addr {
+        let (addr, _) = metrics::serve(metrics.clone(), metrics_addr.as_str())?;
+        info!(%addr, "Serving metrics");
+    }
+    if let Some(index_path) = &cli.idempotency_index {
+        let index = IdempotencyIndex::open(index_path)?;
+        debug!(entries = index.len(), "Opened idempotency index");
+        transaction_manager = transaction_manager.with_idempotency_index(index);
+    }
+
+    if let Some(limits_path) = &cli.overdraft_limits {
+        let mut limits_rdr = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(limits_path)?;
+        for result in limits_rdr.deserialize::<(u16, f64)>() {
+            let (client, limit) = result?;
+            transaction_manager.set_overdraft_limit(client, limit)?;
+        }
+    }
+
+    let mut skipped_rows = Vec::new();
+    let started = Instant::now();
+
+    let summaries = process_files(
+        &mut transaction_manager,
+        &input_paths,
+        cli.stop_on_error,
+        &mut skipped_rows,
+    )?;
+    let parse_failures = summaries.iter().map(|summary| summary.unparsable).sum();
+
+    if !skipped_rows.is_empty() {
+        eprintln!("Skipped {} already processed rows:", skipped_rows.len());
+        for (path, row, transaction) in &skipped_rows {
+            eprintln!("  {} row {row}: {transaction:?}", path.display());
+        }
+    }
+
+    let rule_matches = transaction_manager.rule_matches();
+    if !rule_matches.is_empty() {
+        eprintln!("{} rule matches:", rule_matches.len());
+        for matched in rule_matches {
+            eprintln!("  {matched}");
+        }
+    }
+
+    let pending = transaction_manager.history().pending();
+    if !pending.is_empty() {
+        eprintln!("{} transactions pending review:", pending.len());
+        for transaction in pending {
+            eprintln!("  {transaction:?}");
+        }
+    }
+
+    if let Some(stats_path) = &cli.stats {
+        let stats = transaction_manager
+            .run_stats()
+            .with_parse_failures(parse_failures)
+            .with_elapsed(started.elapsed());
+
+        if stats_path.as_os_str() == "-" {
+            eprintln!("{stats}");
+        } else {
+            fs::write(stats_path, serde_json::to_string_pretty(&stats)?)?;
+        }
+    }
+
+    if let Some(metrics_path) = &cli.metrics {
+        fs::write(metrics_path, metrics.render())?;
+    }
+
+    Ok(transaction_manager)
+}
+
+// Applies the files at `paths` in turn, reporting on each of them, until one of them has
+// errors if `stop_on_error` is set
+fn process_files(
+    transaction_manager: &mut TransactionManager,
+    paths: &[PathBuf],
+    stop_on_error: bool,
+    skipped_rows: &mut Vec<(PathBuf, usize, Transaction)>,
+) -> Result<Vec<FileSummary>, Box<dyn Error>> {
+    let mut summaries = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let summary = process_file(transaction_manager, path, skipped_rows)?;
+        eprintln!("{summary}");
+        let has_errors = summary.has_errors();
+        summaries.push(summary);
+
+        if stop_on_error && has_errors {
+            eprintln!("Stopping after errors in {}", path.display());
+            break;
+        }
+    }
+
+    Ok(summaries)
+}
+
+// Applies every row of the file at `path`, noting rows which were already processed
+// in `skipped_rows`
+fn process_file(
+    transaction_manager: &mut TransactionManager,
+    path: &Path,
+    skipped_rows: &mut Vec<(PathBuf, usize, Transaction)>,
+) -> Result<FileSummary, Box<dyn Error>> {
+    let mut rdr = transaction_reader(path)?;
+
+    let mut summary = FileSummary::new(path);
+    let _entered = info_span!("file", path = %path.display()).entered();
+
+    for (row, result) in rdr.deserialize::<Transaction>().enumerate() {
+        summary.rows += 1;
+        // Rows are numbered from 1, after the header
+        let row = row + 1;
+
+        let transaction = match result {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                error!(row, error = %e, "Unable to parse this transaction");
+                summary.unparsable += 1;
+                continue;
+            }
+        };
+
+        match transaction_manager.record_transaction(&transaction) {
+            Ok(()) => summary.applied += 1,
+            Err(TransactionManagerError::AlreadyApplied { .. }) => {
+                summary.already_applied += 1;
+                skipped_rows.push((path.to_path_buf(), row, transaction));
+            }
+            Err(TransactionManagerError::HeldForReview { tx, rule, .. }) => {
+                summary.held += 1;
+                // Reported along with everything else still pending at the end
+                info!(row, tx, rule, "Transaction held for review");
+            }
+            Err(e @ TransactionManagerError::IdempotencyIndexWriteFailed { .. }) => {
+                // The transaction was applied, only a replay of it wouldn't be recognized
+                summary.applied += 1;
+                warn!(row, tx = transaction.tx(), error = %e, "Idempotency index write failed");
+            }
+            Err(e) => {
+                summary.failed += 1;
+                warn!(row, tx = transaction.tx(), error = %e, "Transaction failed to be inserted");
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+fn transaction_reader(path: &Path) -> Result<csv::Reader<Box<dyn Read>>, Box<dyn Error>> {
+    let file = inputs::open_csv(path)?;
+    // Configuring to make sure we trim all whitespace from headers and fields
+    Ok(ReaderBuilder::new().trim(csv::Trim::All).from_reader(file))
+}
+
+// Reads every row of the file at `path`, skipping those which can't be parsed
+fn read_transactions(path: &Path) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let _entered = info_span!("file", path = %path.display()).entered();
+
+    let mut transactions = Vec::new();
+    for (row, result) in transaction_reader(path)?.deserialize().enumerate() {
+        match result {
+            Ok(transaction) => transactions.push(transaction),
+            Err(e) => error!(row = row + 1, error = %e, "Unable to parse this transaction"),
+        }
+    }
+
+    Ok(transactions)
+}
+
+// Rule files are JSON if they end in `.json`, and TOML otherwise
+fn load_rules(path: &Path) -> Result<RulesEngine, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        Ok(serde_json::from_str(&contents)?)
+    } else {
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_files_stops_on_error() {
+        let dir = std::env::temp_dir().join(format!("process-files-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // A row starting with `#` is a row like any other, rather than a comment
+        let paths = vec![dir.join("shard-01.csv"), dir.join("shard-02.csv")];
+        fs::write(
+            &paths[0],
+            "# sequence: 1\ntype,client,tx,amount\ndeposit,1,1,10.0\n#deposit,1,2,10.0\n",
+        )
+        .unwrap();
+        fs::write(&paths[1], "type,client,tx,amount\ndeposit,1,3,5.0\n").unwrap();
+
+        let mut transaction_manager = TransactionManager::new();
+        let summaries =
+            process_files(&mut transaction_manager, &paths, true, &mut Vec::new()).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].rows, 2);
+        assert_eq!(summaries[0].applied, 1);
+        assert_eq!(summaries[0].unparsable, 1);
+
+        let mut transaction_manager = TransactionManager::new();
+        let summaries =
+            process_files(&mut transaction_manager, &paths, false, &mut Vec::new()).unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[1].applied, 1);
+        assert_eq!(
+            transaction_manager.retrieve_client_balances().to_csv(),
+            "client,available,held,total,locked\n1,15,0,15,false\n"
+        );
+    }
+}
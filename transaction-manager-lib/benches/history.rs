@@ -0,0 +1,144 @@
+//! Compares `TransactionHistory` against storing every `Transaction` in a
+//! `HashMap<u32, Transaction>`, which is how history used to be kept.
+//!
+//! Run with `cargo bench -p transaction-manager-lib --bench history`, optionally passing the
+//! number of transactions to insert, e.g. `-- 10000000`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+use transaction_manager_lib::history::{DepositRecord, TransactionHistory, WithdrawalRecord};
+use transaction_manager_lib::transactions::{Deposit, Transaction, Withdrawal};
+
+const DEFAULT_TRANSACTIONS: u32 = 2_000_000;
+
+// Roughly what we see in the feeds: three deposits for every withdrawal
+fn is_deposit(tx: u32) -> bool {
+    tx & 3 != 0
+}
+
+fn client(tx: u32) -> u16 {
+    (tx % u16::MAX as u32) as u16
+}
+
+fn amount(tx: u32) -> f64 {
+    (tx % 10_000) as f64 / 100.0
+}
+
+struct Measurement {
+    insert: Duration,
+    lookup: Duration,
+    approx_bytes: usize,
+}
+
+/// Buckets hold the entry plus one control byte, and `capacity` is 7/8 of the buckets
+fn approx_map_bytes(capacity: usize, entry_size: usize) -> usize {
+    capacity * 8 / 7 * (entry_size + 1)
+}
+
+fn bench_hash_map(transactions: u32) -> Measurement {
+    let start = Instant::now();
+    let mut history = HashMap::new();
+    for tx in 0..transactions {
+        let transaction = if is_deposit(tx) {
+            Transaction::Deposit(Deposit::new(client(tx), tx, amount(tx)))
+        } else {
+            Transaction::Withdrawal(Withdrawal::new(client(tx), tx, amount(tx)))
+        };
+        history.insert(tx, transaction);
+    }
+    let insert = start.elapsed();
+
+    let start = Instant::now();
+    let mut found = 0.0;
+    for tx in 0..transactions {
+        if let Some(Transaction::Deposit(d)) = history.get(&tx) {
+            found += d.amount;
+        }
+    }
+    black_box(found);
+    let lookup = start.elapsed();
+
+    Measurement {
+        insert,
+        lookup,
+        approx_bytes: approx_map_bytes(history.capacity(), size_of::<(u32, Transaction)>()),
+    }
+}
+
+fn bench_transaction_history(transactions: u32) -> Measurement {
+    let start = Instant::now();
+    let mut history = TransactionHistory::new();
+    for tx in 0..transactions {
+        if is_deposit(tx) {
+            history.insert_deposit(tx, DepositRecord::new(client(tx), amount(tx)));
+        } else {
+            history.insert_withdrawal(tx, WithdrawalRecord::new(client(tx), amount(tx)));
+        }
+    }
+    let insert = start.elapsed();
+
+    let start = Instant::now();
+    let mut found = 0.0;
+    for tx in 0..transactions {
+        if let Some(d) = history.deposit(tx) {
+            found += d.amount;
+        }
+    }
+    black_box(found);
+    let lookup = start.elapsed();
+
+    // TransactionHistory doesn't expose its maps' capacities, so size them as if
+    // they'd been built the same way
+    let deposits = (0..transactions).filter(|tx| is_deposit(*tx)).count();
+    let mut deposit_map = HashMap::<u32, DepositRecord>::new();
+    deposit_map.reserve(deposits);
+    let mut withdrawal_map = HashMap::<u32, WithdrawalRecord>::new();
+    withdrawal_map.reserve(transactions as usize - deposits);
+
+    Measurement {
+        insert,
+        lookup,
+        approx_bytes: approx_map_bytes(deposit_map.capacity(), size_of::<(u32, DepositRecord)>())
+            + approx_map_bytes(
+                withdrawal_map.capacity(),
+                size_of::<(u32, WithdrawalRecord)>(),
+            ),
+    }
+}
+
+fn report(name: &str, transactions: u32, m: &Measurement) {
+    println!(
+        "{name:<28} insert: {:>10.2?}  lookup: {:>10.2?}  ~{:>6.1} MiB  ~{:>5.1} bytes/tx",
+        m.insert,
+        m.lookup,
+        m.approx_bytes as f64 / (1024.0 * 1024.0),
+        m.approx_bytes as f64 / transactions as f64,
+    );
+}
+
+fn main() {
+    let transactions = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_TRANSACTIONS);
+
+    println!("{transactions} transactions, 3 deposits per withdrawal");
+    println!(
+        "entry sizes: (u32, Transaction) = {} bytes, (u32, DepositRecord) = {} bytes",
+        size_of::<(u32, Transaction)>(),
+        size_of::<(u32, DepositRecord)>(),
+    );
+
+    report(
+        "HashMap<u32, Transaction>",
+        transactions,
+        &bench_hash_map(transactions),
+    );
+    report(
+        "TransactionHistory",
+        transactions,
+        &bench_transaction_history(transactions),
+    );
+}
@@ -1,4 +1,10 @@
-use std::collections::{HashMap, HashSet};
+use crate::csv_rows::read_rows;
+use crate::currency::Currency;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
 
 // TODO: Consider _not_ implementing Clone here when I've
 // better fleshed out how to return a reference to this
@@ -9,16 +15,45 @@ pub struct ClientBalanceRegistry {
     pub client_balances: HashMap<u16, ClientBalance>,
 }
 
-#[derive(Clone, Debug, Default, PartialEq)]
-pub struct ClientBalance {
+/// Funds a client holds in a single currency
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Funds {
     pub available: f64,
     pub held: f64,
     pub total: f64,
+}
+
+impl Funds {
+    pub fn new(available: f64, held: f64, total: f64) -> Self {
+        Self {
+            available,
+            held,
+            total,
+        }
+    }
+}
+
+/// A client's account, with its funds kept apart per currency. Funds of transactions
+/// which didn't specify a currency are kept under `None`.
+///
+/// Locking applies to the whole account, whichever currency the chargeback was in.
+///
+/// Withdrawals, fees, exchanges and transfers may take the available funds which aren't
+/// in a specific currency down to `-overdraft_limit`, but no further. Funds in specific
+/// currencies can't go below zero, so that the limit can't be drawn once per currency.
+/// Disputes taking the client beyond it are handled according to the manager's
+/// `NegativeBalancePolicy`, and by default are still applied, after which the client
+/// can't take out any more funds until they're back within it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientBalance {
+    pub funds: BTreeMap<Option<Currency>, Funds>,
     pub locked: bool,
     pub disputed_transactions: HashSet<u32>,
+    pub overdraft_limit: f64,
 }
 
 impl ClientBalance {
+    /// Creates an account holding funds only in the unspecified currency
     pub fn new(
         available: f64,
         held: f64,
@@ -27,16 +62,53 @@ impl ClientBalance {
         disputed_transactions: HashSet<u32>,
     ) -> Self {
         Self {
-            available,
-            held,
-            total,
+            funds: BTreeMap::from([(None, Funds::new(available, held, total))]),
             locked,
             disputed_transactions,
+            overdraft_limit: 0.0,
         }
     }
+
+    pub fn with_overdraft_limit(mut self, overdraft_limit: f64) -> Self {
+        self.overdraft_limit = overdraft_limit;
+        self
+    }
+
+    pub fn with_funds(mut self, currency: Option<Currency>, funds: Funds) -> Self {
+        self.funds.insert(currency, funds);
+        self
+    }
+
+    /// How far below zero the available funds in `currency` may go, which is only ever
+    /// the overdraft limit for funds not in a specific currency
+    pub fn overdraft_limit_in(&self, currency: Option<Currency>) -> f64 {
+        match currency {
+            None => self.overdraft_limit,
+            Some(_) => 0.0,
+        }
+    }
+
+    /// Funds held in `currency`, all zero if the client never held any
+    pub fn funds(&self, currency: Option<Currency>) -> Funds {
+        self.funds.get(&currency).copied().unwrap_or_default()
+    }
 }
 
 impl ClientBalanceRegistry {
+    /// Copy of only the accounts of `clients`
+    pub(crate) fn subset(&self, clients: &HashSet<u16>) -> Self {
+        let client_balances = clients
+            .iter()
+            .filter_map(|client| {
+                self.client_balances
+                    .get(client)
+                    .map(|balance| (*client, balance.clone()))
+            })
+            .collect();
+
+        Self { client_balances }
+    }
+
     pub fn new() -> Self {
         Self {
             client_balances: HashMap::new(),
@@ -48,18 +120,94 @@ impl ClientBalanceRegistry {
         Self { client_balances }
     }
 
+    /// Writes one row per client and currency. The `currency` column is only added once
+    /// any client holds funds in a specific currency, so that the output of single currency
+    /// input is unchanged.
     pub fn to_csv(&self) -> String {
+        let multi_currency = self
+            .client_balances
+            .values()
+            .any(|balance| balance.funds.keys().any(Option::is_some));
+
         let mut csv_data = String::new();
-        csv_data.push_str("client,available,held,total,locked\n"); // CSV header
+        // CSV header
+        if multi_currency {
+            csv_data.push_str("client,currency,available,held,total,locked\n");
+        } else {
+            csv_data.push_str("client,available,held,total,locked\n");
+        }
+
+        let no_funds = BTreeMap::from([(None, Funds::default())]);
 
         for (client_id, balance) in &self.client_balances {
-            let row = format!(
-                "{},{},{},{},{}\n",
-                client_id, balance.available, balance.held, balance.total, balance.locked
-            );
-            csv_data.push_str(&row);
+            // Accounts which never held any funds still get a row
+            let funds = if balance.funds.is_empty() {
+                &no_funds
+            } else {
+                &balance.funds
+            };
+
+            for (currency, funds) in funds {
+                let row = if multi_currency {
+                    format!(
+                        "{},{},{},{},{},{}\n",
+                        client_id,
+                        currency.map(|c| c.to_string()).unwrap_or_default(),
+                        funds.available,
+                        funds.held,
+                        funds.total,
+                        balance.locked
+                    )
+                } else {
+                    format!(
+                        "{},{},{},{},{}\n",
+                        client_id, funds.available, funds.held, funds.total, balance.locked
+                    )
+                };
+                csv_data.push_str(&row);
+            }
         }
 
         csv_data
     }
+
+    /// Loads balances written by `to_csv`, or by anything else with a header naming the
+    /// `client`, `available`, `held`, `total` and `locked` columns, in any order. The
+    /// `currency` column is optional, and other columns are ignored. Without a header, the
+    /// columns are taken to be those five in that order. Blank lines and lines starting
+    /// with `#` are skipped.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        let rows: Vec<BalanceRow> = read_rows(
+            reader,
+            &["client", "available", "held", "total", "locked"],
+            "balance",
+        )?;
+
+        let mut registry = Self::new();
+        for row in rows {
+            let balance = registry.client_balances.entry(row.client).or_default();
+            balance
+                .funds
+                .insert(row.currency, Funds::new(row.available, row.held, row.total));
+            balance.locked |= row.locked;
+        }
+
+        Ok(registry)
+    }
+}
+
+// A row of a file of balances
+#[derive(Deserialize)]
+struct BalanceRow {
+    client: u16,
+    #[serde(default)]
+    currency: Option<Currency>,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
 }
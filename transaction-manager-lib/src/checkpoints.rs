@@ -0,0 +1,169 @@
+use crate::balance::ClientBalanceRegistry;
+use crate::history::TransactionHistory;
+use crate::idempotency::IdempotencyIndex;
+use crate::rules::RulesEngine;
+use crate::transactions::Transaction;
+use std::fmt;
+
+/// Point of a run to query the balances at, see `TransactionManager::balances_as_of`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsOf {
+    /// Once the given number of transactions had been recorded, whether or not they
+    /// were applied. `Sequence(0)` is before any of them.
+    Sequence(usize),
+    /// Once every transaction up to the given time, in seconds since the Unix epoch, had
+    /// been recorded. Transactions without a timestamp are taken to have happened at the
+    /// latest timestamp seen before them.
+    Timestamp(u64),
+}
+
+impl fmt::Display for AsOf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsOf::Sequence(sequence) => write!(f, "transaction {sequence}"),
+            AsOf::Timestamp(timestamp) => write!(f, "timestamp {timestamp}"),
+        }
+    }
+}
+
+/// Copy of everything a manager changes while recording transactions, which is enough to
+/// carry on recording from where it was taken
+#[derive(Debug)]
+pub(crate) struct ManagerState {
+    pub(crate) balances: ClientBalanceRegistry,
+    pub(crate) history: TransactionHistory,
+    pub(crate) idempotency_index: Option<IdempotencyIndex>,
+    pub(crate) rules: Option<RulesEngine>,
+    pub(crate) now: Option<u64>,
+}
+
+impl ManagerState {
+    // Not `Clone`, as the copy of the idempotency index is only kept in memory
+    pub(crate) fn copy(&self) -> Self {
+        Self {
+            balances: self.balances.clone(),
+            history: self.history.clone(),
+            idempotency_index: self
+                .idempotency_index
+                .as_ref()
+                .map(IdempotencyIndex::in_memory_copy),
+            rules: self.rules.clone(),
+            now: self.now,
+        }
+    }
+}
+
+/// Something which changed a manager's state, which replaying them in order does again
+#[derive(Debug)]
+pub(crate) enum Change {
+    /// Recorded, whether or not it was applied
+    Recorded(Transaction),
+    /// By `TransactionManager::advance_time`
+    ClockMoved(u64),
+    /// By `TransactionManager::set_overdraft_limit`
+    OverdraftLimitSet { client: u16, limit: f64 },
+}
+
+/// Every change made to a manager, along with copies of its state from which the
+/// balances at any point can be rebuilt by replaying the changes since the closest copy
+/// before it.
+///
+/// Copies are only taken after transactions, at least `interval` of them apart, and at
+/// least as far apart as there had been transactions before the last copy. As the state
+/// grows with the transactions, the copies together never take more than about twice
+/// the memory of the latest state, at the cost of replaying up to half of the
+/// transactions. The changes themselves include a copy of every transaction, so they
+/// take memory in proportion to the number of transactions on top of that.
+#[derive(Debug)]
+pub(crate) struct Checkpoints {
+    interval: usize,
+    changes: Vec<Change>,
+    // Index among the changes of every recorded transaction, along with the manager's
+    // clock once it had been recorded
+    recorded: Vec<(usize, Option<u64>)>,
+    // Keyed by how many changes and transactions there had been when they were taken,
+    // in order
+    states: Vec<(usize, usize, ManagerState)>,
+}
+
+impl Checkpoints {
+    pub(crate) fn new(interval: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            changes: Vec::new(),
+            recorded: Vec::new(),
+            states: Vec::new(),
+        }
+    }
+
+    /// How many transactions have been recorded
+    pub(crate) fn len(&self) -> usize {
+        self.recorded.len()
+    }
+
+    /// Whether the state before the first change still has to be copied, which is what
+    /// every replay starts from
+    pub(crate) fn needs_initial_state(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Whether enough transactions have been recorded since the last copy of the state
+    pub(crate) fn is_due(&self) -> bool {
+        match self.states.last() {
+            Some((_, recorded, _)) => {
+                self.recorded.len() - recorded >= self.interval.max(*recorded)
+            }
+            None => true,
+        }
+    }
+
+    pub(crate) fn record(&mut self, t: &Transaction, now: Option<u64>) {
+        self.recorded.push((self.changes.len(), now));
+        self.changes.push(Change::Recorded(t.clone()));
+    }
+
+    /// Notes a change made other than by recording a transaction
+    pub(crate) fn note(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    /// Keeps `state` as of now
+    pub(crate) fn insert(&mut self, state: ManagerState) {
+        self.states
+            .push((self.changes.len(), self.recorded.len(), state));
+    }
+
+    /// How many transactions had been recorded by `timestamp`
+    pub(crate) fn sequence_at(&self, timestamp: u64) -> usize {
+        // The clock never goes backwards, so the transactions are in time order
+        self.recorded
+            .partition_point(|(_, now)| !matches!(now, Some(now) if *now > timestamp))
+    }
+
+    /// The latest copy of the state taken at or before `sequence` transactions had been
+    /// recorded, along with the changes made between it and the next transaction
+    pub(crate) fn replay_from(
+        &self,
+        sequence: usize,
+    ) -> Option<(&ManagerState, impl Iterator<Item = &Change>)> {
+        let end = match self.recorded.get(sequence) {
+            Some((next, _)) => *next,
+            None if sequence == self.recorded.len() => self.changes.len(),
+            None => return None,
+        };
+
+        let closest = self
+            .states
+            .partition_point(|(taken_at, _, _)| *taken_at <= end)
+            .checked_sub(1)?;
+        let (taken_at, _, state) = &self.states[closest];
+
+        Some((state, self.changes[*taken_at..end].iter()))
+    }
+
+    /// Every copy of the state kept
+    #[cfg(test)]
+    pub(crate) fn states(&self) -> impl Iterator<Item = &ManagerState> {
+        self.states.iter().map(|(_, _, state)| state)
+    }
+}
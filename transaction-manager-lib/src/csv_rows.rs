@@ -0,0 +1,110 @@
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde::de::DeserializeOwned;
+use std::io::{self, Read};
+
+/// Reads every row of a CSV file into a `T`, by the names of its columns.
+///
+/// The first line is taken to be a header if it names every one of `columns`, in any
+/// case and order, along with any others. Otherwise the file has no header and its
+/// columns are `columns`, in that order. Fields are trimmed, and blank lines and lines
+/// starting with `#` are skipped. Errors name the line of the row, and `what` it was
+/// meant to be.
+pub(crate) fn read_rows<T, R>(reader: R, columns: &[&str], what: &str) -> io::Result<Vec<T>>
+where
+    T: DeserializeOwned,
+    R: Read,
+{
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(reader);
+
+    let mut records = reader.records();
+    let mut rows = Vec::new();
+
+    let Some(first) = records.next().transpose().map_err(|e| invalid(what, &e))? else {
+        return Ok(rows);
+    };
+
+    let names: StringRecord = first.iter().map(str::to_ascii_lowercase).collect();
+    let is_header = columns
+        .iter()
+        .all(|column| names.iter().any(|name| name == *column));
+    let header: StringRecord = if is_header {
+        names
+    } else {
+        columns.iter().collect()
+    };
+    let first = (!is_header).then_some(Ok(first));
+
+    for record in first.into_iter().chain(records) {
+        let record = record.map_err(|e| invalid(what, &e))?;
+        rows.push(
+            record
+                .deserialize(Some(&header))
+                .map_err(|e| invalid(what, &e))?,
+        );
+    }
+
+    Ok(rows)
+}
+
+fn invalid(what: &str, e: &csv::Error) -> io::Error {
+    let line = e
+        .position()
+        .map(|position| format!(" on line {}", position.line()))
+        .unwrap_or_default();
+
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid {what}{line}: {e}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Row {
+        client: u16,
+        amount: f64,
+    }
+
+    #[test]
+    fn test_read_rows_with_and_without_header() {
+        let with_header: Vec<Row> = read_rows(
+            "Amount, note, CLIENT\n# comment\n1.5, first, 1\n\n2, , 2\n".as_bytes(),
+            &["client", "amount"],
+            "row",
+        )
+        .unwrap();
+        let without_header: Vec<Row> =
+            read_rows("1, 1.5\n2,2\n".as_bytes(), &["client", "amount"], "row").unwrap();
+
+        let expected = vec![
+            Row {
+                client: 1,
+                amount: 1.5,
+            },
+            Row {
+                client: 2,
+                amount: 2.0,
+            },
+        ];
+        assert_eq!(with_header, expected);
+        assert_eq!(without_header, expected);
+
+        let err = read_rows::<Row, _>(
+            "client,amount\n1,1.5\n2,lots\n".as_bytes(),
+            &["client", "amount"],
+            "row",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("Invalid row on line 3: "));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::csv_rows::read_rows;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Three letter currency code, e.g. `EUR`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // Only ever built from ASCII letters
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseCurrencyError(String);
+
+impl fmt::Display for ParseCurrencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid currency code: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseCurrencyError {}
+
+/// Accepts any three ASCII letters, in any case, e.g. `eur` is parsed as `EUR`
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code: [u8; 3] = s
+            .trim()
+            .as_bytes()
+            .try_into()
+            .map_err(|_| ParseCurrencyError(s.to_string()))?;
+
+        if !code.iter().all(u8::is_ascii_alphabetic) {
+            return Err(ParseCurrencyError(s.to_string()));
+        }
+
+        Ok(Currency(code.map(|c| c.to_ascii_uppercase())))
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Currency, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let code = String::deserialize(deserializer)?;
+
+        code.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Source of exchange rates for `Exchange` transactions
+pub trait RateProvider: Send + Sync {
+    /// How many units of `to` one unit of `from` buys, if known
+    fn rate(&self, from: Currency, to: Currency) -> Option<f64>;
+}
+
+/// Fixed set of exchange rates, e.g. loaded from a file, so that exchanges can be processed
+/// without going online.
+///
+/// A rate given for one direction is also used, inverted, for the other direction unless
+/// that has a rate of its own.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StaticRates {
+    rates: HashMap<(Currency, Currency), f64>,
+}
+
+impl StaticRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rate(mut self, from: Currency, to: Currency, rate: f64) -> Self {
+        self.rates.insert((from, to), rate);
+        self
+    }
+
+    /// Loads rates from a CSV file with a `from,to,rate` line per rate, e.g. `EUR,USD,1.08`.
+    /// A header line, blank lines and lines starting with `#` are skipped.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        let rows: Vec<RateRow> = read_rows(reader, &["from", "to", "rate"], "exchange rate")?;
+
+        let mut rates = Self::new();
+        for row in rows {
+            if !row.rate.is_finite() || row.rate <= 0.0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid exchange rate from {} to {}: rate must be positive",
+                        row.from, row.to
+                    ),
+                ));
+            }
+
+            rates = rates.with_rate(row.from, row.to, row.rate);
+        }
+
+        Ok(rates)
+    }
+}
+
+// A row of a file of exchange rates
+#[derive(Deserialize)]
+struct RateRow {
+    from: Currency,
+    to: Currency,
+    rate: f64,
+}
+
+impl RateProvider for StaticRates {
+    fn rate(&self, from: Currency, to: Currency) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+
+        self.rates
+            .get(&(from, to))
+            .copied()
+            .or_else(|| self.rates.get(&(to, from)).map(|rate| 1.0 / rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_static_rates_from_reader() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "usd".parse().unwrap();
+        let gbp: Currency = "GBP".parse().unwrap();
+
+        let rates =
+            StaticRates::from_reader("from,to,rate\nEUR,USD,1.25\n\n# comment\n".as_bytes())
+                .unwrap();
+
+        assert_eq!(rates.rate(eur, usd), Some(1.25));
+        assert_eq!(rates.rate(usd, eur), Some(0.8));
+        assert_eq!(rates.rate(eur, gbp), None);
+
+        assert!(StaticRates::from_reader("EUR,USD,-1\n".as_bytes()).is_err());
+        assert!(StaticRates::from_reader("EURO,USD,1\n".as_bytes()).is_err());
+    }
+}
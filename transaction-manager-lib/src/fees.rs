@@ -0,0 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How a fee is worked out from the amount of the transaction it's levied on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeeRate {
+    Flat(f64),
+    /// Percentage of the transaction amount, e.g. `1.5` for 1.5%
+    Percentage(f64),
+}
+
+impl FeeRate {
+    pub fn fee_for(&self, amount: f64) -> f64 {
+        match self {
+            FeeRate::Flat(fee) => *fee,
+            FeeRate::Percentage(percentage) => amount * percentage / 100.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseFeeRateError(String);
+
+impl fmt::Display for ParseFeeRateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid fee rate: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseFeeRateError {}
+
+/// Parses `0.5` as a flat fee of 0.5 and `1.5%` as 1.5% of the transaction amount
+impl FromStr for FeeRate {
+    type Err = ParseFeeRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (value, percentage) = match s.strip_suffix('%') {
+            Some(value) => (value.trim(), true),
+            None => (s, false),
+        };
+
+        let value = value
+            .parse::<f64>()
+            .map_err(|e| ParseFeeRateError(format!("{s}: {e}")))?;
+
+        if !value.is_finite() || value < 0.0 {
+            return Err(ParseFeeRateError(format!("{s}: must not be negative")));
+        }
+
+        if percentage {
+            Ok(FeeRate::Percentage(value))
+        } else {
+            Ok(FeeRate::Flat(value))
+        }
+    }
+}
+
+/// Fees levied automatically on top of deposits and withdrawals.
+///
+/// Withdrawal fees are taken on top of the amount withdrawn, so the client needs enough
+/// available funds for both. Deposit fees are taken out of the deposit, and never more
+/// than the deposit itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeSchedule {
+    pub deposit: Option<FeeRate>,
+    pub withdrawal: Option<FeeRate>,
+}
+
+impl FeeSchedule {
+    pub fn new(deposit: Option<FeeRate>, withdrawal: Option<FeeRate>) -> Self {
+        Self {
+            deposit,
+            withdrawal,
+        }
+    }
+
+    pub fn deposit_fee(&self, amount: f64) -> f64 {
+        self.deposit
+            .map_or(0.0, |rate| rate.fee_for(amount).min(amount))
+    }
+
+    pub fn withdrawal_fee(&self, amount: f64) -> f64 {
+        self.withdrawal.map_or(0.0, |rate| rate.fee_for(amount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fee_rate() {
+        assert_eq!("0.5".parse(), Ok(FeeRate::Flat(0.5)));
+        assert_eq!(" 1.5 % ".parse(), Ok(FeeRate::Percentage(1.5)));
+        assert!("-1".parse::<FeeRate>().is_err());
+        assert!("abc%".parse::<FeeRate>().is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! Random but reproducible streams of transactions, along with the balances they should
+//! result in, for load testing and fuzzing the manager, see `WorkloadGenerator`.
+
+use crate::balance::{ClientBalanceRegistry, Funds};
+use crate::transactions::{Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal};
+use std::collections::{HashMap, HashSet};
+
+/// Generates deposits and withdrawals between a number of clients, with some of the
+/// deposits disputed and then resolved or charged back, and optionally rows which are
+/// invalid. The same seed always gives the same workload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkloadGenerator {
+    seed: u64,
+    clients: u16,
+    transactions: usize,
+    dispute_rate: f64,
+    resolve_rate: f64,
+    chargeback_rate: f64,
+    invalid_rate: f64,
+}
+
+/// A generated input CSV, and what the manager should make of it
+#[derive(Clone, Debug)]
+pub struct Workload {
+    /// With a `type,client,tx,amount` header
+    pub csv: String,
+    /// Every row of the CSV which can be parsed, in order
+    pub transactions: Vec<Transaction>,
+    /// Balances once every row has been recorded, with the manager's default policies
+    pub expected: ClientBalanceRegistry,
+}
+
+impl WorkloadGenerator {
+    /// 1000 rows between 100 clients, of which 2% are disputes, 1% resolves, 0.5%
+    /// chargebacks and none invalid
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            clients: 100,
+            transactions: 1000,
+            dispute_rate: 0.02,
+            resolve_rate: 0.01,
+            chargeback_rate: 0.005,
+            invalid_rate: 0.0,
+        }
+    }
+
+    pub fn with_clients(mut self, clients: u16) -> Self {
+        self.clients = clients.max(1);
+        self
+    }
+
+    /// Number of rows, including the invalid ones
+    pub fn with_transactions(mut self, transactions: usize) -> Self {
+        self.transactions = transactions;
+        self
+    }
+
+    /// Share of the rows which dispute an earlier deposit
+    pub fn with_dispute_rate(mut self, rate: f64) -> Self {
+        self.dispute_rate = rate;
+        self
+    }
+
+    /// Share of the rows which resolve an open dispute
+    pub fn with_resolve_rate(mut self, rate: f64) -> Self {
+        self.resolve_rate = rate;
+        self
+    }
+
+    /// Share of the rows which charge back an open dispute
+    pub fn with_chargeback_rate(mut self, rate: f64) -> Self {
+        self.chargeback_rate = rate;
+        self
+    }
+
+    /// Share of the rows which either can't be parsed or are always rejected, e.g.
+    /// deposits of negative amounts or reusing a transaction id
+    pub fn with_invalid_rate(mut self, rate: f64) -> Self {
+        self.invalid_rate = rate;
+        self
+    }
+
+    pub fn generate(&self) -> Workload {
+        let mut rng = SplitMix64(self.seed);
+        let mut model = ReferenceModel::default();
+
+        let mut csv = String::from("type,client,tx,amount\n");
+        let mut transactions = Vec::with_capacity(self.transactions);
+
+        // Every row uses up an id, even those which refer to an earlier transaction
+        for tx in (1..).take(self.transactions) {
+            let client = rng.below(u64::from(self.clients)) as u16 + 1;
+
+            // Rows which aren't possible yet, e.g. resolves before any disputes, are
+            // deposits or withdrawals instead
+            let mut roll = rng.unit();
+            let row = if roll < self.invalid_rate {
+                self.invalid_row(&mut rng, &model, client, tx)
+            } else {
+                roll -= self.invalid_rate;
+                if roll < self.dispute_rate && !model.deposits.is_empty() {
+                    let (tx, owner) =
+                        model.deposits[rng.below(model.deposits.len() as u64) as usize];
+                    Row::Valid(Transaction::Dispute(Dispute::new(owner, tx)))
+                } else if roll < self.dispute_rate + self.resolve_rate && !model.open.is_empty() {
+                    let (tx, owner) = model.open[rng.below(model.open.len() as u64) as usize];
+                    Row::Valid(Transaction::Resolve(Resolve::new(owner, tx)))
+                } else if roll < self.dispute_rate + self.resolve_rate + self.chargeback_rate
+                    && !model.open.is_empty()
+                {
+                    let (tx, owner) = model.open[rng.below(model.open.len() as u64) as usize];
+                    Row::Valid(Transaction::Chargeback(Chargeback::new(owner, tx)))
+                } else if rng.below(5) < 3 {
+                    Row::Valid(Transaction::Deposit(Deposit::new(client, tx, rng.amount())))
+                } else {
+                    Row::Valid(Transaction::Withdrawal(Withdrawal::new(
+                        client,
+                        tx,
+                        rng.amount() / 2.0,
+                    )))
+                }
+            };
+
+            match row {
+                Row::Valid(t) => {
+                    csv.push_str(&to_row(&t));
+                    model.record(&t);
+                    transactions.push(t);
+                }
+                Row::Unparsable(line) => csv.push_str(&line),
+            }
+        }
+
+        Workload {
+            csv,
+            transactions,
+            expected: model.balances,
+        }
+    }
+
+    fn invalid_row(
+        &self,
+        rng: &mut SplitMix64,
+        model: &ReferenceModel,
+        client: u16,
+        tx: u32,
+    ) -> Row {
+        match rng.below(5) {
+            0 => Row::Unparsable(format!("bonus,{client},{tx},{}\n", rng.amount())),
+            1 => Row::Unparsable(format!("deposit,{client},{tx},twelve\n")),
+            2 => Row::Valid(Transaction::Deposit(Deposit::new(
+                client,
+                tx,
+                -rng.amount(),
+            ))),
+            3 if !model.deposits.is_empty() => {
+                let (reused, _) = model.deposits[rng.below(model.deposits.len() as u64) as usize];
+                Row::Valid(Transaction::Deposit(Deposit::new(
+                    client,
+                    reused,
+                    rng.amount(),
+                )))
+            }
+            // No transaction has this id, and none will as it's used up by this row
+            _ => Row::Valid(Transaction::Dispute(Dispute::new(client, tx))),
+        }
+    }
+}
+
+impl Workload {
+    /// The expected balances, as the manager would write them
+    pub fn expected_csv(&self) -> String {
+        self.expected.to_csv()
+    }
+}
+
+enum Row {
+    Valid(Transaction),
+    Unparsable(String),
+}
+
+// Only the types of transaction the generator makes are written out
+fn to_row(t: &Transaction) -> String {
+    match t.amount() {
+        Some(amount) if !t.kind().refers_to_earlier() => {
+            format!("{},{},{},{amount}\n", t.type_name(), t.client(), t.tx())
+        }
+        _ => format!("{},{},{},\n", t.type_name(), t.client(), t.tx()),
+    }
+}
+
+// Deliberately simpler than the manager, covering only deposits, withdrawals and full
+// disputes of deposits, so that it can be trusted to say what the manager should do
+#[derive(Debug, Default)]
+struct ReferenceModel {
+    balances: ClientBalanceRegistry,
+    applied: HashSet<u32>,
+    // Amounts of the deposits, and whether they're disputed
+    amounts: HashMap<u32, (u16, f64, bool)>,
+    // In the order they were applied, so that picking one is reproducible
+    deposits: Vec<(u32, u16)>,
+    open: Vec<(u32, u16)>,
+}
+
+impl ReferenceModel {
+    fn record(&mut self, t: &Transaction) {
+        let client = t.client();
+        let tx = t.tx();
+
+        let duplicate = !t.kind().refers_to_earlier() && self.applied.contains(&tx);
+        let negative = t.amount().is_some_and(|amount| amount < 0.0);
+        let locked = self
+            .balances
+            .client_balances
+            .get(&client)
+            .is_some_and(|balance| balance.locked);
+        let unowned = t.kind().refers_to_earlier()
+            && self
+                .amounts
+                .get(&tx)
+                .is_some_and(|(owner, _, _)| *owner != client);
+        if duplicate || negative || locked || unowned {
+            return;
+        }
+
+        // As with the manager, the account is opened by anything which gets this far
+        let balance = self.balances.client_balances.entry(client).or_default();
+
+        match t {
+            Transaction::Deposit(d) => {
+                let funds = balance.funds.entry(None).or_default();
+                funds.total += d.amount;
+                funds.available += d.amount;
+
+                self.applied.insert(tx);
+                self.amounts.insert(tx, (client, d.amount, false));
+                self.deposits.push((tx, client));
+            }
+            Transaction::Withdrawal(w) => {
+                let funds = balance.funds.entry(None).or_default();
+                if funds.available < w.amount {
+                    return;
+                }
+                funds.total -= w.amount;
+                funds.available -= w.amount;
+
+                self.applied.insert(tx);
+            }
+            Transaction::Dispute(_) => {
+                let Some((_, amount, disputed)) = self.amounts.get_mut(&tx) else {
+                    return;
+                };
+                if *disputed {
+                    return;
+                }
+                *disputed = true;
+
+                let funds = balance.funds.entry(None).or_default();
+                funds.available -= *amount;
+                funds.held += *amount;
+
+                balance.disputed_transactions.insert(tx);
+                self.open.push((tx, client));
+            }
+            Transaction::Resolve(_) | Transaction::Chargeback(_) => {
+                if !balance.disputed_transactions.remove(&tx) {
+                    return;
+                }
+                self.open.retain(|(open, _)| *open != tx);
+
+                let (_, amount, disputed) = self.amounts.get_mut(&tx).unwrap();
+                *disputed = false;
+
+                let funds: &mut Funds = balance.funds.entry(None).or_default();
+                funds.held -= *amount;
+                if matches!(t, Transaction::Resolve(_)) {
+                    funds.available += *amount;
+                } else {
+                    funds.total -= *amount;
+                    balance.locked = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Written out rather than taken from a crate, so that a seed gives the same workload
+// whatever the version of its dependencies
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    // In [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Between 0.0001 and 1000, to four decimal places
+    fn amount(&mut self) -> f64 {
+        (self.below(10_000_000) + 1) as f64 / 10_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reconciliation::Reconciliation;
+    use crate::transaction_manager::TransactionManager;
+
+    #[test]
+    fn test_generated_workload_matches_manager() {
+        let generator = WorkloadGenerator::new(7)
+            .with_clients(20)
+            .with_transactions(5000)
+            .with_dispute_rate(0.1)
+            .with_resolve_rate(0.04)
+            .with_chargeback_rate(0.01)
+            .with_invalid_rate(0.05);
+
+        let workload = generator.generate();
+        assert_eq!(workload.csv.lines().count(), 5001);
+        assert!(workload.transactions.len() < 5000);
+        assert!(workload
+            .expected
+            .client_balances
+            .values()
+            .any(|balance| balance.locked));
+
+        let mut tm = TransactionManager::new();
+        for t in &workload.transactions {
+            let _ = tm.record_transaction(t);
+        }
+        let actual = tm.retrieve_client_balances();
+
+        assert!(Reconciliation::new(&workload.expected, &actual, 0.0).is_reconciled());
+        assert_eq!(actual, workload.expected);
+
+        let again = generator.generate();
+        assert_eq!(again.csv, workload.csv);
+        assert_eq!(again.expected, workload.expected);
+        assert_ne!(WorkloadGenerator::new(8).generate().csv, workload.csv);
+    }
+}
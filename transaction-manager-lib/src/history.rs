@@ -1,30 +1,404 @@
+use crate::currency::Currency;
 use crate::transactions::Transaction;
-use std::collections::HashMap;
-use std::ops::{Deref, DerefMut};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-#[derive(Debug)]
+/// Index standing for funds which aren't in any specific currency
+const NO_CURRENCY: u8 = 0;
+
+/// Where a disputable transaction is in its dispute lifecycle, or whether a withdrawal has
+/// been reversed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TransactionState {
+    Settled,
+    Disputed,
+    ChargedBack,
+    Reversed,
+}
+
+/// Everything we need to remember about a deposit in order to dispute it later.
+///
+/// Transfers are disputed by the client who received them in the same way as deposits,
+/// so they're kept as deposits into `client`, with the client who sent them kept on the
+/// side, see `TransactionHistory::transfer_sender`.
+///
+/// The currency is kept as an index into the history's table of currencies, see
+/// `TransactionHistory::currency`.
+///
+/// Packed to a 4 byte alignment so that, together with its `u32` key, an entry takes
+/// up 16 bytes in the map rather than the 24 it would with the natural alignment
+/// of the `f64`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C, packed(4))]
+pub struct DepositRecord {
+    pub amount: f64,
+    pub client: u16,
+    pub state: TransactionState,
+    pub currency: u8,
+}
+
+impl DepositRecord {
+    pub fn new(client: u16, amount: f64) -> Self {
+        Self {
+            amount,
+            client,
+            state: TransactionState::Settled,
+            currency: NO_CURRENCY,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: u8) -> Self {
+        self.currency = currency;
+        self
+    }
+}
+
+/// Everything we need to remember about a withdrawal in order to reverse it later. Laid out
+/// the same as a `DepositRecord`, and so just as small.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C, packed(4))]
+pub struct WithdrawalRecord {
+    pub amount: f64,
+    pub client: u16,
+    pub state: TransactionState,
+    pub currency: u8,
+}
+
+impl WithdrawalRecord {
+    pub fn new(client: u16, amount: f64) -> Self {
+        Self {
+            amount,
+            client,
+            state: TransactionState::Settled,
+            currency: NO_CURRENCY,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: u8) -> Self {
+        self.currency = currency;
+        self
+    }
+}
+
+/// How much of a deposit is currently held by disputes, and how much of it has been
+/// charged back. Only kept for deposits which have been disputed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DisputedAmounts {
+    pub held: f64,
+    pub charged_back: f64,
+}
+
+impl DisputedAmounts {
+    /// What's left of `amount` which could still be disputed
+    pub fn undisputed(&self, amount: f64) -> f64 {
+        amount - self.held - self.charged_back
+    }
+}
+
+/// History of the transactions which have been applied.
+///
+/// Only deposits can be disputed and only deposits and withdrawals can be reversed, so
+/// only those are kept as full records. For any other transaction we only remember its
+/// id, so that duplicates can be rejected.
+///
+/// Memory bound, per transaction, is the size of its map entry plus one control byte,
+/// divided by the map's load factor (at most 7/8, at least 7/16 right after growing):
+/// * deposit or withdrawal: 16 + 1 bytes, so between ~20 and ~39 bytes
+/// * any other transaction: 4 + 1 bytes, so between ~6 and ~12 bytes
+///
+/// For comparison, a `HashMap<u32, Transaction>` takes 56 + 1 bytes per entry
+/// for every transaction, so between ~65 and ~130 bytes. See `benches/history.rs`.
+///
+/// Everything else is only needed for some transactions, so it's kept on the side and
+/// only for the transactions which need it:
+/// * timestamps of deposits which had one, to enforce a `DisputePolicy`: 16 + 1 bytes
+/// * the sender of a transfer: 8 + 1 bytes
+/// * the `DisputedAmounts` of a deposit once it's been disputed: 20 + 1 bytes
+/// * fees levied on a transaction by a `FeeSchedule`: 16 + 1 bytes
+/// * the rate an exchange was made at, for auditing: 16 + 1 bytes
+///
+/// Transactions held for review haven't been applied yet, so they're kept in full, in
+/// the order they were held, until they're approved or declined. Their ids aren't taken
+/// until then.
+#[derive(Clone, Debug, Default)]
 pub struct TransactionHistory {
-    history: HashMap<u32, Transaction>,
+    deposits: HashMap<u32, DepositRecord>,
+    withdrawals: HashMap<u32, WithdrawalRecord>,
+    non_disputable: HashSet<u32>,
+    deposit_timestamps: HashMap<u32, u64>,
+    dispute_timestamps: HashMap<u32, u64>,
+    disputed_amounts: HashMap<u32, DisputedAmounts>,
+    levied_fees: HashMap<u32, f64>,
+    transfer_senders: HashMap<u32, u16>,
+    exchange_rates: HashMap<u32, f64>,
+    pending: Vec<Transaction>,
+    // Index 0 is `NO_CURRENCY`, so the currency with index i is at i - 1
+    currencies: Vec<Currency>,
 }
 
 impl TransactionHistory {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(deposits: usize, non_disputable: usize) -> Self {
         Self {
-            history: HashMap::new(),
+            deposits: HashMap::with_capacity(deposits),
+            non_disputable: HashSet::with_capacity(non_disputable),
+            ..Self::default()
         }
     }
-}
 
-impl Deref for TransactionHistory {
-    type Target = HashMap<u32, Transaction>;
+    /// Copy of only what's kept about `txs`, along with every open dispute which may expire,
+    /// the transactions held for review and the table of currencies
+    pub(crate) fn subset(&self, txs: &HashSet<u32>) -> Self {
+        let mut txs = txs.clone();
+        txs.extend(self.dispute_timestamps.keys());
+        txs.extend(self.pending.iter().map(Transaction::tx));
 
-    fn deref(&self) -> &Self::Target {
-        &self.history
+        Self {
+            deposits: only(&self.deposits, &txs),
+            withdrawals: only(&self.withdrawals, &txs),
+            non_disputable: txs
+                .iter()
+                .filter(|tx| self.non_disputable.contains(tx))
+                .copied()
+                .collect(),
+            deposit_timestamps: only(&self.deposit_timestamps, &txs),
+            dispute_timestamps: self.dispute_timestamps.clone(),
+            disputed_amounts: only(&self.disputed_amounts, &txs),
+            levied_fees: only(&self.levied_fees, &txs),
+            transfer_senders: only(&self.transfer_senders, &txs),
+            exchange_rates: only(&self.exchange_rates, &txs),
+            pending: self.pending.clone(),
+            currencies: self.currencies.clone(),
+        }
+    }
+
+    /// Every client a kept transaction belongs to, was sent by or is held for
+    pub(crate) fn clients(&self) -> HashSet<u16> {
+        let mut clients: HashSet<u16> = self
+            .deposits
+            .values()
+            .map(|record| record.client)
+            .chain(self.withdrawals.values().map(|record| record.client))
+            .chain(self.transfer_senders.values().copied())
+            .collect();
+        for t in &self.pending {
+            clients.insert(t.client());
+            clients.extend(t.counterparty());
+        }
+        clients
+    }
+
+    /// Index to keep in a `DepositRecord` for `currency`, or `None` if the table of
+    /// currencies is already full, as at most 255 currencies can be told apart
+    pub fn currency_index(&mut self, currency: Option<Currency>) -> Option<u8> {
+        let Some(currency) = currency else {
+            return Some(NO_CURRENCY);
+        };
+
+        let position = match self.currencies.iter().position(|c| *c == currency) {
+            Some(position) => position,
+            None => {
+                self.currencies.push(currency);
+                self.currencies.len() - 1
+            }
+        };
+
+        match u8::try_from(position + 1) {
+            Ok(index) => Some(index),
+            Err(_) => {
+                self.currencies.pop();
+                None
+            }
+        }
+    }
+
+    /// The currency behind an index kept in a `DepositRecord`
+    pub fn currency(&self, index: u8) -> Option<Currency> {
+        match index {
+            NO_CURRENCY => None,
+            index => self.currencies.get(index as usize - 1).copied(),
+        }
+    }
+
+    /// Whether a transaction with this id has already been applied, or is held for review
+    pub fn contains(&self, tx: u32) -> bool {
+        self.deposits.contains_key(&tx)
+            || self.withdrawals.contains_key(&tx)
+            || self.non_disputable.contains(&tx)
+            || self.pending_transaction(tx).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.deposits.len() + self.withdrawals.len() + self.non_disputable.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deposits.is_empty() && self.withdrawals.is_empty() && self.non_disputable.is_empty()
+    }
+
+    pub fn insert_deposit(&mut self, tx: u32, record: DepositRecord) {
+        self.deposits.insert(tx, record);
+    }
+
+    pub fn insert_non_disputable(&mut self, tx: u32) {
+        self.non_disputable.insert(tx);
     }
-}
 
-impl DerefMut for TransactionHistory {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.history
+    pub fn deposit(&self, tx: u32) -> Option<&DepositRecord> {
+        self.deposits.get(&tx)
     }
+
+    pub fn deposit_mut(&mut self, tx: u32) -> Option<&mut DepositRecord> {
+        self.deposits.get_mut(&tx)
+    }
+
+    pub fn insert_withdrawal(&mut self, tx: u32, record: WithdrawalRecord) {
+        self.withdrawals.insert(tx, record);
+    }
+
+    pub fn withdrawal(&self, tx: u32) -> Option<&WithdrawalRecord> {
+        self.withdrawals.get(&tx)
+    }
+
+    pub fn withdrawal_mut(&mut self, tx: u32) -> Option<&mut WithdrawalRecord> {
+        self.withdrawals.get_mut(&tx)
+    }
+
+    /// Keeps a transfer from `from_client` as a deposit into the receiving client
+    pub fn insert_transfer(&mut self, tx: u32, from_client: u16, record: DepositRecord) {
+        self.deposits.insert(tx, record);
+        self.transfer_senders.insert(tx, from_client);
+    }
+
+    /// The client who sent `tx`, if it's a transfer
+    pub fn transfer_sender(&self, tx: u32) -> Option<u16> {
+        self.transfer_senders.get(&tx).copied()
+    }
+
+    /// Keeps an exchange, which can't be disputed, along with the rate it was made at
+    pub fn insert_exchange(&mut self, tx: u32, rate: f64) {
+        self.non_disputable.insert(tx);
+        self.exchange_rates.insert(tx, rate);
+    }
+
+    /// The rate `tx` was made at, if it's an exchange
+    pub fn exchange_rate(&self, tx: u32) -> Option<f64> {
+        self.exchange_rates.get(&tx).copied()
+    }
+
+    /// The deposit along with its disputed amounts, if it's ever been disputed
+    pub fn disputed_deposit_mut(
+        &mut self,
+        tx: u32,
+    ) -> Option<(&mut DepositRecord, &mut DisputedAmounts)> {
+        let record = self.deposits.get_mut(&tx)?;
+        let amounts = self.disputed_amounts.get_mut(&tx)?;
+
+        Some((record, amounts))
+    }
+
+    /// How much of `tx` is held or has been charged back, all zero if it's never been
+    /// disputed
+    pub fn disputed_amounts(&self, tx: u32) -> DisputedAmounts {
+        self.disputed_amounts.get(&tx).copied().unwrap_or_default()
+    }
+
+    /// Holds `amount` more of the deposit `tx` for a dispute which has been applied
+    pub fn hold_disputed(&mut self, tx: u32, amount: f64) {
+        if let Some(record) = self.deposits.get_mut(&tx) {
+            record.state = TransactionState::Disputed;
+            self.disputed_amounts.entry(tx).or_default().held += amount;
+        }
+    }
+
+    /// Sum of everything charged back so far, by the currency of the deposits
+    pub fn charged_back_by_currency(&self) -> BTreeMap<Option<Currency>, f64> {
+        let mut charged_back = BTreeMap::new();
+        for (tx, amounts) in &self.disputed_amounts {
+            let Some(deposit) = self.deposits.get(tx) else {
+                continue;
+            };
+            // Summing from 0.0 rather than -0.0, which would show up as such
+            *charged_back
+                .entry(self.currency(deposit.currency))
+                .or_insert(0.0) += amounts.charged_back;
+        }
+        charged_back
+    }
+
+    /// Remembers the fee levied on `tx`, so that only what was actually credited or debited is
+    /// disputed or reversed
+    pub fn insert_levied_fee(&mut self, tx: u32, fee: f64) {
+        self.levied_fees.insert(tx, fee);
+    }
+
+    pub fn levied_fee(&self, tx: u32) -> Option<f64> {
+        self.levied_fees.get(&tx).copied()
+    }
+
+    pub fn insert_deposit_timestamp(&mut self, tx: u32, timestamp: u64) {
+        self.deposit_timestamps.insert(tx, timestamp);
+    }
+
+    pub fn deposit_timestamp(&self, tx: u32) -> Option<u64> {
+        self.deposit_timestamps.get(&tx).copied()
+    }
+
+    /// Remembers when the dispute of `tx` was opened, unless it's already open
+    pub fn open_dispute(&mut self, tx: u32, timestamp: u64) {
+        self.dispute_timestamps.entry(tx).or_insert(timestamp);
+    }
+
+    pub fn close_dispute(&mut self, tx: u32) {
+        self.dispute_timestamps.remove(&tx);
+    }
+
+    /// Disputes which were opened at or before `opened_by`, in transaction id order
+    pub fn disputes_opened_by(&self, opened_by: u64) -> Vec<u32> {
+        let mut disputes: Vec<u32> = self
+            .dispute_timestamps
+            .iter()
+            .filter(|(_, opened_at)| **opened_at <= opened_by)
+            .map(|(tx, _)| *tx)
+            .collect();
+        disputes.sort_unstable();
+        disputes
+    }
+
+    /// Keeps `t` back until it's approved or declined
+    pub fn insert_pending(&mut self, t: Transaction) {
+        self.pending.push(t);
+    }
+
+    /// Transactions held for review, in the order they were held
+    pub fn pending(&self) -> &[Transaction] {
+        &self.pending
+    }
+
+    /// The earliest transaction with id `tx` held for review
+    pub fn pending_transaction(&self, tx: u32) -> Option<&Transaction> {
+        self.pending.iter().find(|t| t.tx() == tx)
+    }
+
+    /// Takes the earliest transaction with id `tx` out of those held for review, along with
+    /// its place among them
+    pub fn take_pending(&mut self, tx: u32) -> Option<(usize, Transaction)> {
+        let position = self.pending.iter().position(|t| t.tx() == tx)?;
+        Some((position, self.pending.remove(position)))
+    }
+
+    /// Holds `t` again, back in the place `take_pending` took it from
+    pub fn restore_pending(&mut self, position: usize, t: Transaction) {
+        self.pending.insert(position.min(self.pending.len()), t);
+    }
+}
+
+// The entries of `map` for `txs`
+fn only<V: Copy>(map: &HashMap<u32, V>, txs: &HashSet<u32>) -> HashMap<u32, V> {
+    txs.iter()
+        .filter_map(|tx| map.get(tx).map(|value| (*tx, *value)))
+        .collect()
 }
@@ -0,0 +1,227 @@
+use crate::csv_rows::read_rows;
+use crate::transactions::Transaction;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Index of every row which has been recorded, whether it was applied or rejected,
+/// along with what became of it.
+///
+/// Rows are keyed on everything they say: type, client, transaction id, amount,
+/// currencies, counterparty and timestamp. So e.g. a second partial dispute of a deposit
+/// for another amount is a row of its own, while a re-sent row is recognized whatever
+/// became of it the first time. Rows which are the same in every field can't be told
+/// apart, so only the first of them is applied.
+///
+/// When backed by a file, every recorded row is appended to it as a line of its key
+/// followed by its outcome, so that the index survives restarts and a re-sent file can
+/// be replayed as a no-op.
+#[derive(Debug, Default)]
+pub struct IdempotencyIndex {
+    // Outcomes by key, which are `accepted` or the code of the error
+    recorded: HashMap<String, String>,
+    journal: Option<File>,
+}
+
+impl IdempotencyIndex {
+    /// Creates an index which is only kept in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the index persisted at `path`, creating the file if it doesn't exist yet
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let journal = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        let entries: Vec<IndexEntry> = read_rows(&journal, &COLUMNS, "idempotency index entry")?;
+        let recorded = entries
+            .into_iter()
+            .map(|entry| (entry.key(), entry.outcome))
+            .collect();
+
+        Ok(Self {
+            recorded,
+            journal: Some(journal),
+        })
+    }
+
+    // Index appending to `journal`, e.g. one which can't be written to
+    #[cfg(test)]
+    pub(crate) fn with_journal(journal: File) -> Self {
+        Self {
+            recorded: HashMap::new(),
+            journal: Some(journal),
+        }
+    }
+
+    /// Copy of the index which is only kept in memory, so that whatever is recorded in it
+    /// isn't persisted
+    pub fn in_memory_copy(&self) -> Self {
+        Self {
+            recorded: self.recorded.clone(),
+            journal: None,
+        }
+    }
+
+    /// In-memory copy of only what became of `transactions`
+    pub(crate) fn subset(&self, transactions: &[Transaction]) -> Self {
+        let recorded = transactions
+            .iter()
+            .map(index_key)
+            .filter_map(|key| {
+                let outcome = self.recorded.get(&key)?.clone();
+                Some((key, outcome))
+            })
+            .collect();
+
+        Self {
+            recorded,
+            journal: None,
+        }
+    }
+
+    pub fn contains(&self, t: &Transaction) -> bool {
+        self.recorded.contains_key(&index_key(t))
+    }
+
+    /// What became of `t` when it was recorded, if it was
+    pub fn outcome(&self, t: &Transaction) -> Option<&str> {
+        self.recorded.get(&index_key(t)).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.recorded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recorded.is_empty()
+    }
+
+    /// Notes what became of the transaction, appending it to the backing file if there is
+    /// one
+    pub fn record(&mut self, t: &Transaction, outcome: &str) -> io::Result<()> {
+        let key = index_key(t);
+
+        if let Some(journal) = self.journal.as_mut() {
+            writeln!(journal, "{key},{outcome}")?;
+            journal.flush()?;
+        }
+
+        self.recorded.insert(key, outcome.to_string());
+
+        Ok(())
+    }
+}
+
+// Of the lines of the backing file, which has no header
+const COLUMNS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "currency",
+    "counterparty",
+    "timestamp",
+    "outcome",
+];
+
+// A line of the backing file, with the fields of the key kept as they were written
+#[derive(Deserialize)]
+struct IndexEntry {
+    r#type: String,
+    client: String,
+    tx: String,
+    amount: String,
+    currency: String,
+    counterparty: String,
+    timestamp: String,
+    outcome: String,
+}
+
+impl IndexEntry {
+    fn key(&self) -> String {
+        [
+            &self.r#type,
+            &self.client,
+            &self.tx,
+            &self.amount,
+            &self.currency,
+            &self.counterparty,
+            &self.timestamp,
+        ]
+        .map(String::as_str)
+        .join(",")
+    }
+}
+
+// `type,client,tx,amount,currency,counterparty,timestamp`, with the counterparty of an
+// exchange being the currency converted into, and fields the row doesn't have empty
+fn index_key(t: &Transaction) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+
+    let counterparty = match t {
+        Transaction::Exchange(e) => Some(e.to_currency.to_string()),
+        _ => t.counterparty().map(|client| client.to_string()),
+    };
+
+    format!(
+        "{},{},{},{},{},{},{}",
+        t.type_name(),
+        t.client(),
+        t.tx(),
+        optional(t.amount().map(|amount| amount.to_string())),
+        optional(t.currency().map(|currency| currency.to_string())),
+        optional(counterparty),
+        optional(t.timestamp().map(|timestamp| timestamp.to_string())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{Deposit, Dispute};
+    use std::fs;
+
+    #[test]
+    fn test_index_survives_reopening() {
+        let path = std::env::temp_dir().join(format!(
+            "transaction-manager-idempotency-{}.idx",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let deposit = Transaction::Deposit(Deposit::new(1, 1, 32.0));
+        let dispute = Transaction::Dispute(Dispute::new(1, 1));
+        let partial_dispute =
+            Transaction::Dispute(Dispute::new(1, 1).with_amount(5.5).with_timestamp(7));
+
+        {
+            let mut index = IdempotencyIndex::open(&path).unwrap();
+            assert!(index.is_empty());
+            index.record(&deposit, "accepted").unwrap();
+            index
+                .record(&partial_dispute, "insufficient_funds")
+                .unwrap();
+        }
+
+        let index = IdempotencyIndex::open(&path).unwrap();
+        assert!(index.contains(&deposit));
+        assert_eq!(index.outcome(&partial_dispute), Some("insufficient_funds"));
+        assert!(!index.contains(&dispute));
+        assert!(!index.contains(&Transaction::Deposit(Deposit::new(1, 1, 33.0))));
+        assert_eq!(index.len(), 2);
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "deposit,1,1,32,,,,accepted\ndispute,1,1,5.5,,,7,insufficient_funds\n"
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,18 @@
 pub mod balance;
-mod history;
+pub mod checkpoints;
+mod csv_rows;
+pub mod currency;
+pub mod fees;
+pub mod generator;
+pub mod history;
+pub mod idempotency;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod policy;
+pub mod reconciliation;
+pub mod rules;
+pub mod simulation;
+pub mod stats;
 pub mod transaction_manager;
 pub mod transactions;
+pub mod validators;
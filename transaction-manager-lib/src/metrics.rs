@@ -0,0 +1,233 @@
+//! Metrics of a running `TransactionManager`, in the Prometheus text exposition format.
+//!
+//! Only built with the `metrics` feature.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the buckets of the processing latency histogram
+const LATENCY_BUCKETS: [f64; 8] = [1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 0.1, 1.0, 10.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // Count of observations per bucket, with the last one standing for +Inf
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    // Keyed by transaction type and outcome, which is `accepted` or the code of the error
+    transactions: BTreeMap<(&'static str, &'static str), u64>,
+    latency: Histogram,
+    open_disputes: usize,
+    locked_accounts: usize,
+    history_size: usize,
+}
+
+/// Counters, gauges and histograms fed by a `TransactionManager`, see
+/// `TransactionManager::with_metrics`. Can be shared with other threads, e.g. to be
+/// served with `serve`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_transaction(
+        &self,
+        type_name: &'static str,
+        outcome: &'static str,
+        latency: Duration,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        *state.transactions.entry((type_name, outcome)).or_default() += 1;
+        state.latency.observe(latency.as_secs_f64());
+    }
+
+    pub fn set_history_size(&self, history_size: usize) {
+        self.state.lock().unwrap().history_size = history_size;
+    }
+
+    pub fn set_disputes(&self, open_disputes: usize, locked_accounts: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.open_disputes = open_disputes;
+        state.locked_accounts = locked_accounts;
+    }
+
+    /// All metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut text = String::new();
+
+        // Writing to a String never fails
+        let _ = writeln!(
+            text,
+            "# HELP transactions_total Transactions recorded, by type and outcome"
+        );
+        let _ = writeln!(text, "# TYPE transactions_total counter");
+        for ((type_name, outcome), count) in &state.transactions {
+            let _ = writeln!(
+                text,
+                "transactions_total{{type=\"{type_name}\",outcome=\"{outcome}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(
+            text,
+            "# HELP transaction_processing_seconds Time taken to record a transaction"
+        );
+        let _ = writeln!(text, "# TYPE transaction_processing_seconds histogram");
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&state.latency.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                text,
+                "transaction_processing_seconds_bucket{{le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            text,
+            "transaction_processing_seconds_bucket{{le=\"+Inf\"}} {}",
+            state.latency.count
+        );
+        let _ = writeln!(
+            text,
+            "transaction_processing_seconds_sum {}",
+            state.latency.sum
+        );
+        let _ = writeln!(
+            text,
+            "transaction_processing_seconds_count {}",
+            state.latency.count
+        );
+
+        for (name, help, value) in [
+            (
+                "open_disputes",
+                "Disputes currently open",
+                state.open_disputes,
+            ),
+            (
+                "locked_accounts",
+                "Accounts locked by a chargeback",
+                state.locked_accounts,
+            ),
+            (
+                "history_size",
+                "Transactions kept in the history",
+                state.history_size,
+            ),
+        ] {
+            let _ = writeln!(text, "# HELP {name} {help}");
+            let _ = writeln!(text, "# TYPE {name} gauge");
+            let _ = writeln!(text, "{name} {value}");
+        }
+
+        text
+    }
+}
+
+/// How long a connection to `serve` can take to send its request line, and then to take
+/// the response
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Serves `metrics` over HTTP on `addr`, at any path, from a background thread which
+/// handles one connection at a time. Returns the address actually bound, e.g. when `addr`
+/// asked for any free port.
+pub fn serve<A: ToSocketAddrs>(
+    metrics: Arc<Metrics>,
+    addr: A,
+) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+
+            // So that a client which never sends anything, or never reads the response,
+            // can't hold up the others for long
+            if stream.set_read_timeout(Some(TIMEOUT)).is_err()
+                || stream.set_write_timeout(Some(TIMEOUT)).is_err()
+            {
+                continue;
+            }
+
+            let _ = respond(&metrics, &stream, &stream);
+        }
+    });
+
+    Ok((local_addr, handle))
+}
+
+// Only the request line matters, whatever was asked for gets the metrics
+fn respond<R: Read, W: Write>(metrics: &Metrics, request: R, mut response: W) -> io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(request).read_line(&mut request_line)?;
+
+    let body = metrics.render();
+    write!(
+        response,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_and_respond() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_transaction("deposit", "accepted", Duration::from_micros(5));
+        metrics.record_transaction("withdrawal", "insufficient_funds", Duration::from_secs(20));
+        metrics.set_disputes(2, 1);
+
+        let text = metrics.render();
+        assert!(text.contains("transactions_total{type=\"deposit\",outcome=\"accepted\"} 1\n"));
+        assert!(text.contains("transaction_processing_seconds_bucket{le=\"0.00001\"} 1\n"));
+        assert!(text.contains("transaction_processing_seconds_bucket{le=\"10\"} 1\n"));
+        assert!(text.contains("transaction_processing_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("open_disputes 2\n"));
+        assert!(text.contains("locked_accounts 1\n"));
+
+        let mut response = Vec::new();
+        respond(
+            &metrics,
+            "GET /metrics HTTP/1.1\r\n\r\n".as_bytes(),
+            &mut response,
+        )
+        .unwrap();
+        let response = String::from_utf8(response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains(&format!("Content-Length: {}\r\n", text.len())));
+        assert!(response.ends_with(&text));
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Limits on how long after a deposit it may be disputed, and on how long a dispute may
+/// stay open. Ages are measured in seconds, using the optional `timestamp` of transactions.
+///
+/// Transactions without a timestamp are never considered late, and disputes opened
+/// before any timestamp has been seen never expire.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DisputePolicy {
+    /// Disputes raised more than this long after the deposit are rejected
+    pub max_dispute_age: Option<u64>,
+    /// Disputes still open this long after they were raised are resolved automatically
+    pub max_dispute_duration: Option<u64>,
+}
+
+impl DisputePolicy {
+    pub fn new(max_dispute_age: Option<u64>, max_dispute_duration: Option<u64>) -> Self {
+        Self {
+            max_dispute_age,
+            max_dispute_duration,
+        }
+    }
+
+    /// Whether a dispute raised at `disputed_at` on a deposit made at `deposited_at` is too late
+    pub fn is_late(&self, deposited_at: u64, disputed_at: u64) -> bool {
+        self.max_dispute_age
+            .is_some_and(|max_age| disputed_at.saturating_sub(deposited_at) > max_age)
+    }
+
+    /// Disputes opened at or before the returned time should be resolved by `now`
+    pub fn expiry_cutoff(&self, now: u64) -> Option<u64> {
+        self.max_dispute_duration
+            .and_then(|max_duration| now.checked_sub(max_duration))
+    }
+}
+
+/// What to do with a dispute which would take the client's available funds below what
+/// they're allowed, i.e. below zero or below their overdraft limit. This happens when the
+/// disputed deposit has already been withdrawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NegativeBalancePolicy {
+    /// Hold the full disputed amount, leaving the available funds negative
+    #[default]
+    AllowNegative,
+    /// Reject the dispute
+    RejectDispute,
+    /// Only hold what's still available. The rest of the deposit stays undisputed, and
+    /// can be disputed again later
+    CapHoldAtAvailable,
+}
+
+impl NegativeBalancePolicy {
+    /// How much of `amount` can be held given `spare` funds, i.e. the available funds plus
+    /// any overdraft limit, or `None` if the dispute should be rejected
+    pub fn hold_for(&self, amount: f64, spare: f64) -> Option<f64> {
+        match self {
+            NegativeBalancePolicy::AllowNegative => Some(amount),
+            NegativeBalancePolicy::RejectDispute => (amount <= spare).then_some(amount),
+            NegativeBalancePolicy::CapHoldAtAvailable => Some(amount.min(spare.max(0.0))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseNegativeBalancePolicyError(String);
+
+impl fmt::Display for ParseNegativeBalancePolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid negative balance policy: {}, expected one of allow, reject, cap",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseNegativeBalancePolicyError {}
+
+/// Parses `allow`, `reject` and `cap`
+impl FromStr for NegativeBalancePolicy {
+    type Err = ParseNegativeBalancePolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "allow" => Ok(NegativeBalancePolicy::AllowNegative),
+            "reject" => Ok(NegativeBalancePolicy::RejectDispute),
+            "cap" => Ok(NegativeBalancePolicy::CapHoldAtAvailable),
+            _ => Err(ParseNegativeBalancePolicyError(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negative_balance_policy_hold() {
+        assert_eq!("Cap".parse(), Ok(NegativeBalancePolicy::CapHoldAtAvailable));
+        assert!("deny".parse::<NegativeBalancePolicy>().is_err());
+
+        assert_eq!(
+            NegativeBalancePolicy::AllowNegative.hold_for(10.0, -5.0),
+            Some(10.0)
+        );
+        assert_eq!(
+            NegativeBalancePolicy::RejectDispute.hold_for(10.0, 10.0),
+            Some(10.0)
+        );
+        assert_eq!(
+            NegativeBalancePolicy::RejectDispute.hold_for(10.0, 9.0),
+            None
+        );
+        assert_eq!(
+            NegativeBalancePolicy::CapHoldAtAvailable.hold_for(10.0, 4.0),
+            Some(4.0)
+        );
+        assert_eq!(
+            NegativeBalancePolicy::CapHoldAtAvailable.hold_for(10.0, -5.0),
+            Some(0.0)
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use crate::balance::{ClientBalanceRegistry, Funds};
+use crate::currency::Currency;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Field of a client's funds which differs between the two sets of balances
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Available,
+    Held,
+    Total,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::Available => write!(f, "available"),
+            Field::Held => write!(f, "held"),
+            Field::Total => write!(f, "total"),
+        }
+    }
+}
+
+/// A difference between the expected balances, e.g. those of a ledger they're checked
+/// against, and the actual ones
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// The client only has actual balances
+    MissingFromExpected { client: u16 },
+    /// The client only has expected balances
+    MissingFromActual { client: u16 },
+    /// Funds differ by more than the tolerance. Funds in a currency only one side has
+    /// are taken to be zero on the other
+    FundsMismatch {
+        client: u16,
+        currency: Option<Currency>,
+        field: Field,
+        expected: f64,
+        actual: f64,
+    },
+    LockMismatch {
+        client: u16,
+        expected: bool,
+        actual: bool,
+    },
+}
+
+impl Discrepancy {
+    pub fn client(&self) -> u16 {
+        match self {
+            Discrepancy::MissingFromExpected { client }
+            | Discrepancy::MissingFromActual { client }
+            | Discrepancy::FundsMismatch { client, .. }
+            | Discrepancy::LockMismatch { client, .. } => *client,
+        }
+    }
+
+    /// Stable snake_case name of the discrepancy, for machine-readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Discrepancy::MissingFromExpected { .. } => "missing_from_expected",
+            Discrepancy::MissingFromActual { .. } => "missing_from_actual",
+            Discrepancy::FundsMismatch { .. } => "funds_mismatch",
+            Discrepancy::LockMismatch { .. } => "lock_mismatch",
+        }
+    }
+}
+
+/// How many clients reconciled, and how many didn't
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ReconciliationSummary {
+    pub expected_clients: usize,
+    pub actual_clients: usize,
+    /// Clients on both sides without any discrepancies
+    pub matched_clients: usize,
+    /// Clients on both sides with at least one discrepancy
+    pub mismatched_clients: usize,
+    pub missing_from_expected: usize,
+    pub missing_from_actual: usize,
+    pub funds_mismatches: usize,
+    pub lock_mismatches: usize,
+}
+
+impl fmt::Display for ReconciliationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Reconciled {} expected and {} actual clients:",
+            self.expected_clients, self.actual_clients
+        )?;
+        writeln!(f, "  matched: {}", self.matched_clients)?;
+        writeln!(f, "  mismatched: {}", self.mismatched_clients)?;
+        writeln!(f, "  missing from expected: {}", self.missing_from_expected)?;
+        writeln!(f, "  missing from actual: {}", self.missing_from_actual)?;
+        writeln!(f, "  funds mismatches: {}", self.funds_mismatches)?;
+        write!(f, "  lock mismatches: {}", self.lock_mismatches)
+    }
+}
+
+/// Outcome of matching up two sets of balances by client
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Reconciliation {
+    pub summary: ReconciliationSummary,
+    /// By client, and then currency
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl Reconciliation {
+    /// Compares every client's funds and lock. Funds are only mismatched if they differ by
+    /// more than `tolerance`
+    pub fn new(
+        expected: &ClientBalanceRegistry,
+        actual: &ClientBalanceRegistry,
+        tolerance: f64,
+    ) -> Self {
+        let clients: BTreeSet<u16> = expected
+            .client_balances
+            .keys()
+            .chain(actual.client_balances.keys())
+            .copied()
+            .collect();
+
+        let mut summary = ReconciliationSummary {
+            expected_clients: expected.client_balances.len(),
+            actual_clients: actual.client_balances.len(),
+            ..ReconciliationSummary::default()
+        };
+        let mut discrepancies = Vec::new();
+
+        for client in clients {
+            let (expected_balance, actual_balance) = match (
+                expected.client_balances.get(&client),
+                actual.client_balances.get(&client),
+            ) {
+                (Some(expected_balance), Some(actual_balance)) => {
+                    (expected_balance, actual_balance)
+                }
+                (None, _) => {
+                    summary.missing_from_expected += 1;
+                    discrepancies.push(Discrepancy::MissingFromExpected { client });
+                    continue;
+                }
+                (_, None) => {
+                    summary.missing_from_actual += 1;
+                    discrepancies.push(Discrepancy::MissingFromActual { client });
+                    continue;
+                }
+            };
+
+            let found = discrepancies.len();
+
+            let currencies: BTreeSet<Option<Currency>> = expected_balance
+                .funds
+                .keys()
+                .chain(actual_balance.funds.keys())
+                .copied()
+                .collect();
+
+            for currency in currencies {
+                let expected_funds = expected_balance.funds(currency);
+                let actual_funds = actual_balance.funds(currency);
+
+                for (field, expected, actual) in compare(expected_funds, actual_funds) {
+                    if (expected - actual).abs() > tolerance {
+                        summary.funds_mismatches += 1;
+                        discrepancies.push(Discrepancy::FundsMismatch {
+                            client,
+                            currency,
+                            field,
+                            expected,
+                            actual,
+                        });
+                    }
+                }
+            }
+
+            if expected_balance.locked != actual_balance.locked {
+                summary.lock_mismatches += 1;
+                discrepancies.push(Discrepancy::LockMismatch {
+                    client,
+                    expected: expected_balance.locked,
+                    actual: actual_balance.locked,
+                });
+            }
+
+            if discrepancies.len() == found {
+                summary.matched_clients += 1;
+            } else {
+                summary.mismatched_clients += 1;
+            }
+        }
+
+        Self {
+            summary,
+            discrepancies,
+        }
+    }
+
+    /// Whether the balances matched up
+    pub fn is_reconciled(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    /// Writes one row per discrepancy, with the `field` and the `expected` and `actual`
+    /// values left empty for missing clients. As with `ClientBalanceRegistry::to_csv`, the
+    /// `currency` column is only added if any of the mismatched funds are in a specific
+    /// currency
+    pub fn to_csv(&self) -> String {
+        let multi_currency = self.discrepancies.iter().any(|discrepancy| {
+            matches!(
+                discrepancy,
+                Discrepancy::FundsMismatch {
+                    currency: Some(_),
+                    ..
+                }
+            )
+        });
+
+        let mut csv_data = String::new();
+        if multi_currency {
+            csv_data.push_str("client,currency,kind,field,expected,actual\n");
+        } else {
+            csv_data.push_str("client,kind,field,expected,actual\n");
+        }
+
+        for discrepancy in &self.discrepancies {
+            csv_data.push_str(&discrepancy.client().to_string());
+            csv_data.push(',');
+            if multi_currency {
+                if let Discrepancy::FundsMismatch {
+                    currency: Some(currency),
+                    ..
+                } = discrepancy
+                {
+                    csv_data.push_str(currency.as_str());
+                }
+                csv_data.push(',');
+            }
+            csv_data.push_str(discrepancy.kind());
+
+            let row = match discrepancy {
+                Discrepancy::MissingFromExpected { .. } | Discrepancy::MissingFromActual { .. } => {
+                    ",,,\n".to_string()
+                }
+                Discrepancy::FundsMismatch {
+                    field,
+                    expected,
+                    actual,
+                    ..
+                } => format!(",{field},{expected},{actual}\n"),
+                Discrepancy::LockMismatch {
+                    expected, actual, ..
+                } => format!(",locked,{expected},{actual}\n"),
+            };
+            csv_data.push_str(&row);
+        }
+
+        csv_data
+    }
+}
+
+fn compare(expected: Funds, actual: Funds) -> [(Field, f64, f64); 3] {
+    [
+        (Field::Available, expected.available, actual.available),
+        (Field::Held, expected.held, actual.held),
+        (Field::Total, expected.total, actual.total),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconcile_balances() {
+        let expected = ClientBalanceRegistry::from_reader(
+            "client,available,held,total,locked\n\
+             1,100,0,100,false\n\
+             2,50,10,60,false\n\
+             3,0,0,0,true\n\
+             4,5,0,5,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        // Columns in another order, with one the engine doesn't write
+        let actual = ClientBalanceRegistry::from_reader(
+            "locked,total,held,available,client,branch\n\
+             false,100.00001,0,100.00001,1,north\n\
+             false,60,20,40,2,north\n\
+             false,0,0,0,3,south\n\
+             false,7,0,7,5,south\n\
+             \n"
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let reconciliation = Reconciliation::new(&expected, &actual, 0.0001);
+
+        assert_eq!(
+            reconciliation.summary,
+            ReconciliationSummary {
+                expected_clients: 4,
+                actual_clients: 4,
+                matched_clients: 1,
+                mismatched_clients: 2,
+                missing_from_expected: 1,
+                missing_from_actual: 1,
+                funds_mismatches: 2,
+                lock_mismatches: 1,
+            }
+        );
+        assert!(!reconciliation.is_reconciled());
+        assert_eq!(
+            reconciliation.to_csv(),
+            "client,kind,field,expected,actual\n\
+             2,funds_mismatch,available,50,40\n\
+             2,funds_mismatch,held,10,20\n\
+             3,lock_mismatch,locked,true,false\n\
+             4,missing_from_actual,,,\n\
+             5,missing_from_expected,,,\n"
+        );
+
+        assert!(Reconciliation::new(&expected, &expected, 0.0).is_reconciled());
+
+        assert!(ClientBalanceRegistry::from_reader("client,available\n".as_bytes()).is_err());
+        assert!(ClientBalanceRegistry::from_reader(
+            "client,available,held,total,locked\n1,one,0,0,false\n".as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_reconcile_currencies() {
+        let expected = ClientBalanceRegistry::from_reader(
+            "client,currency,available,held,total,locked\n\
+             1,,10,0,10,false\n\
+             1,EUR,5,0,5,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let actual = ClientBalanceRegistry::from_reader(
+            "client,available,held,total,locked\n1,10,0,10,false\n".as_bytes(),
+        )
+        .unwrap();
+
+        let reconciliation = Reconciliation::new(&expected, &actual, 0.0);
+
+        assert_eq!(
+            reconciliation.to_csv(),
+            "client,currency,kind,field,expected,actual\n\
+             1,EUR,funds_mismatch,available,5,0\n\
+             1,EUR,funds_mismatch,total,5,0\n"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::transactions::Transaction;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+// Amounts are compared as floats, so allow for rounding errors
+const AMOUNT_TOLERANCE: f64 = 1e-9;
+
+/// What happens to a transaction which matches a rule
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Apply the transaction, but report the match
+    Alert,
+    /// Don't apply the transaction yet, keep it for someone to review
+    Hold,
+    /// Don't apply the transaction at all
+    Reject,
+}
+
+/// Suspicious activity a rule looks out for. Clients are only ever compared against their
+/// own transactions
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Condition {
+    /// More than `max` withdrawals among the client's last `window` transactions
+    MaxWithdrawalsPerWindow { max: usize, window: usize },
+    /// A single withdrawal of more than `amount`
+    MaxSingleWithdrawal { amount: f64 },
+    /// More than `max` disputes raised by the client
+    MaxDisputesPerClient { max: usize },
+    /// A withdrawal of at least the whole of a deposit made by the client's previous
+    /// transaction
+    DepositThenFullWithdrawal,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::MaxWithdrawalsPerWindow { max, window } => {
+                write!(f, "max_withdrawals_per_window({max}/{window})")
+            }
+            Condition::MaxSingleWithdrawal { amount } => {
+                write!(f, "max_single_withdrawal({amount})")
+            }
+            Condition::MaxDisputesPerClient { max } => {
+                write!(f, "max_disputes_per_client({max})")
+            }
+            Condition::DepositThenFullWithdrawal => write!(f, "deposit_then_full_withdrawal"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Rule {
+    #[serde(flatten)]
+    pub condition: Condition,
+    pub action: RuleAction,
+}
+
+impl Rule {
+    pub fn new(condition: Condition, action: RuleAction) -> Self {
+        Self { condition, action }
+    }
+}
+
+/// A transaction which matched a rule
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleMatch {
+    pub client: u16,
+    pub tx: u32,
+    pub condition: Condition,
+    pub action: RuleAction,
+}
+
+impl fmt::Display for RuleMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?}: client {} tx {} matched {}",
+            self.action, self.client, self.tx, self.condition
+        )
+    }
+}
+
+/// What's been seen of a client's applied transactions, as far as the rules need to know
+#[derive(Clone, Debug, Default)]
+struct ClientActivity {
+    // Whether each of the most recent transactions was a withdrawal, newest last
+    recent_withdrawals: VecDeque<bool>,
+    // Amount of the previous transaction, if it was a deposit
+    last_deposit: Option<f64>,
+    disputes: usize,
+}
+
+/// Rules checked against every transaction before it's applied. Loaded from a file of
+/// `[[rules]]`, e.g. in TOML:
+///
+/// ```toml
+/// [[rules]]
+/// kind = "max_single_withdrawal"
+/// amount = 1000.0
+/// action = "hold"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RulesEngine {
+    rules: Vec<Rule>,
+    #[serde(skip)]
+    activity: HashMap<u16, ClientActivity>,
+}
+
+impl RulesEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Copy of the rules, along with the activity of only `clients`
+    pub(crate) fn subset(&self, clients: &HashSet<u16>) -> Self {
+        let activity = clients
+            .iter()
+            .filter_map(|client| {
+                self.activity
+                    .get(client)
+                    .map(|activity| (*client, activity.clone()))
+            })
+            .collect();
+
+        Self {
+            rules: self.rules.clone(),
+            activity,
+        }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Every rule `t` would match if it were applied now
+    pub fn evaluate(&self, t: &Transaction) -> Vec<RuleMatch> {
+        let activity = self.activity.get(&t.client());
+
+        self.rules
+            .iter()
+            .filter(|rule| Self::matches(&rule.condition, t, activity))
+            .map(|rule| RuleMatch {
+                client: t.client(),
+                tx: t.tx(),
+                condition: rule.condition.clone(),
+                action: rule.action,
+            })
+            .collect()
+    }
+
+    /// Takes note of `t` having been applied
+    pub fn observe(&mut self, t: &Transaction) {
+        let longest_window = self
+            .rules
+            .iter()
+            .filter_map(|rule| match rule.condition {
+                Condition::MaxWithdrawalsPerWindow { window, .. } => Some(window),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        // For a transfer that's the sender
+        let activity = self.activity.entry(t.client()).or_default();
+
+        activity
+            .recent_withdrawals
+            .push_back(matches!(t, Transaction::Withdrawal(_)));
+        while activity.recent_withdrawals.len() > longest_window {
+            activity.recent_withdrawals.pop_front();
+        }
+
+        activity.last_deposit = match t {
+            Transaction::Deposit(d) => Some(d.amount),
+            _ => None,
+        };
+
+        if let Transaction::Dispute(_) = t {
+            activity.disputes += 1;
+        }
+    }
+
+    fn matches(condition: &Condition, t: &Transaction, activity: Option<&ClientActivity>) -> bool {
+        match (condition, t) {
+            (Condition::MaxWithdrawalsPerWindow { max, window }, Transaction::Withdrawal(_)) => {
+                // The withdrawal being checked is the newest in the window
+                let earlier = activity.map_or(0, |activity| {
+                    activity
+                        .recent_withdrawals
+                        .iter()
+                        .rev()
+                        .take(window.saturating_sub(1))
+                        .filter(|withdrawal| **withdrawal)
+                        .count()
+                });
+                *window > 0 && earlier + 1 > *max
+            }
+            (Condition::MaxSingleWithdrawal { amount }, Transaction::Withdrawal(w)) => {
+                w.amount > *amount + AMOUNT_TOLERANCE
+            }
+            (Condition::MaxDisputesPerClient { max }, Transaction::Dispute(_)) => {
+                activity.map_or(0, |activity| activity.disputes) + 1 > *max
+            }
+            (Condition::DepositThenFullWithdrawal, Transaction::Withdrawal(w)) => activity
+                .and_then(|activity| activity.last_deposit)
+                .is_some_and(|deposited| w.amount >= deposited - AMOUNT_TOLERANCE),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{Deposit, Dispute, Withdrawal};
+
+    #[test]
+    fn test_rules_evaluated_per_client() {
+        let mut engine = RulesEngine::new()
+            .with_rule(Rule::new(
+                Condition::MaxWithdrawalsPerWindow { max: 1, window: 3 },
+                RuleAction::Reject,
+            ))
+            .with_rule(Rule::new(
+                Condition::MaxDisputesPerClient { max: 1 },
+                RuleAction::Hold,
+            ))
+            .with_rule(Rule::new(
+                Condition::DepositThenFullWithdrawal,
+                RuleAction::Alert,
+            ));
+
+        let deposit = Transaction::Deposit(Deposit::new(1, 1, 10.0));
+        assert!(engine.evaluate(&deposit).is_empty());
+        engine.observe(&deposit);
+
+        let withdrawal = Transaction::Withdrawal(Withdrawal::new(1, 2, 10.0));
+        let matched = engine.evaluate(&withdrawal);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].condition, Condition::DepositThenFullWithdrawal);
+        engine.observe(&withdrawal);
+
+        // Another client's activity doesn't count
+        let other_withdrawal = Transaction::Withdrawal(Withdrawal::new(2, 3, 1.0));
+        assert!(engine.evaluate(&other_withdrawal).is_empty());
+
+        let withdrawal = Transaction::Withdrawal(Withdrawal::new(1, 4, 1.0));
+        let matched = engine.evaluate(&withdrawal);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].action, RuleAction::Reject);
+
+        // Once the earlier withdrawal drops out of the window it's fine again
+        engine.observe(&Transaction::Deposit(Deposit::new(1, 5, 5.0)));
+        engine.observe(&Transaction::Deposit(Deposit::new(1, 6, 5.0)));
+        assert!(engine.evaluate(&withdrawal).is_empty());
+
+        let dispute = Transaction::Dispute(Dispute::new(1, 1));
+        assert!(engine.evaluate(&dispute).is_empty());
+        engine.observe(&dispute);
+        assert_eq!(engine.evaluate(&dispute)[0].action, RuleAction::Hold);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::balance::{ClientBalanceRegistry, Funds};
+use crate::currency::Currency;
+use crate::transaction_manager::TransactionManagerError;
+use std::collections::BTreeSet;
+
+/// How one client's funds in one currency, or their account being locked, would change
+#[derive(Clone, Debug, PartialEq)]
+pub struct BalanceDelta {
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub before: Funds,
+    pub after: Funds,
+    pub locked_before: bool,
+    pub locked_after: bool,
+}
+
+/// A transaction which would be rejected, by its index among those simulated
+#[derive(Debug, PartialEq)]
+pub struct Rejection {
+    pub index: usize,
+    pub error: TransactionManagerError,
+}
+
+/// What applying some transactions would do, see `TransactionManager::simulate`
+#[derive(Debug, Default, PartialEq)]
+pub struct Simulation {
+    /// Only the funds which would change, by client and then currency
+    pub deltas: Vec<BalanceDelta>,
+    pub rejections: Vec<Rejection>,
+}
+
+impl Simulation {
+    /// Works out the deltas between the balances before and after the transactions
+    pub fn new(
+        before: &ClientBalanceRegistry,
+        after: &ClientBalanceRegistry,
+        rejections: Vec<Rejection>,
+    ) -> Self {
+        let clients: BTreeSet<u16> = before
+            .client_balances
+            .keys()
+            .chain(after.client_balances.keys())
+            .copied()
+            .collect();
+
+        let mut deltas = Vec::new();
+
+        for client in clients {
+            let balance_before = before.client_balances.get(&client);
+            let balance_after = after.client_balances.get(&client);
+
+            let currencies: BTreeSet<Option<Currency>> = balance_before
+                .into_iter()
+                .chain(balance_after)
+                .flat_map(|balance| balance.funds.keys().copied())
+                .collect();
+            let locked_before = balance_before.is_some_and(|balance| balance.locked);
+            let locked_after = balance_after.is_some_and(|balance| balance.locked);
+
+            for currency in currencies {
+                let funds_before =
+                    balance_before.map_or_else(Funds::default, |balance| balance.funds(currency));
+                let funds_after =
+                    balance_after.map_or_else(Funds::default, |balance| balance.funds(currency));
+
+                if funds_before != funds_after || locked_before != locked_after {
+                    deltas.push(BalanceDelta {
+                        client,
+                        currency,
+                        before: funds_before,
+                        after: funds_after,
+                        locked_before,
+                        locked_after,
+                    });
+                }
+            }
+        }
+
+        Self { deltas, rejections }
+    }
+
+    /// Writes one row per delta, with the value of each column before and after. As with
+    /// `ClientBalanceRegistry::to_csv`, the `currency` column is only added if any of the
+    /// funds are in a specific currency
+    pub fn to_csv(&self) -> String {
+        let multi_currency = self.deltas.iter().any(|delta| delta.currency.is_some());
+
+        let mut csv_data = String::new();
+        if multi_currency {
+            csv_data.push_str("client,currency,");
+        } else {
+            csv_data.push_str("client,");
+        }
+        csv_data.push_str("available_before,available_after,held_before,held_after,total_before,total_after,locked_before,locked_after\n");
+
+        for delta in &self.deltas {
+            csv_data.push_str(&delta.client.to_string());
+            csv_data.push(',');
+            if multi_currency {
+                csv_data.push_str(&delta.currency.map(|c| c.to_string()).unwrap_or_default());
+                csv_data.push(',');
+            }
+            csv_data.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                delta.before.available,
+                delta.after.available,
+                delta.before.held,
+                delta.after.held,
+                delta.before.total,
+                delta.after.total,
+                delta.locked_before,
+                delta.locked_after
+            ));
+        }
+
+        csv_data
+    }
+}
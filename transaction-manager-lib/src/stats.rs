@@ -0,0 +1,279 @@
+use crate::currency::Currency;
+use crate::history::TransactionHistory;
+use crate::transaction_manager::TransactionManagerError;
+use crate::transactions::Transaction;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// How many transactions of one type were processed, and what became of them
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TypeStats {
+    pub processed: usize,
+    pub accepted: usize,
+    pub rejected: usize,
+    /// Rejections by `TransactionManagerError::code`
+    pub rejected_by_error: BTreeMap<&'static str, usize>,
+}
+
+/// Amounts moved in a single currency, or without one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct CurrencyTotals {
+    pub deposited: f64,
+    pub withdrawn: f64,
+    /// Both `fee` transactions and the fees levied on deposits and withdrawals
+    pub fees: f64,
+    pub interest: f64,
+    pub held: f64,
+    pub charged_back: f64,
+}
+
+/// Summary of a run. Totals are kept apart for each currency.
+///
+/// The manager keeps track of what it's been given, see
+/// `TransactionManager::run_stats`, while parse failures and timings are up to whoever
+/// feeds it transactions.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RunStats {
+    /// Keyed by the `type` of the transactions, as given in the CSV
+    pub by_type: BTreeMap<&'static str, TypeStats>,
+    pub parse_failures: usize,
+    pub clients: usize,
+    pub locked_clients: usize,
+    /// Written out as a list, with a `currency` of null for funds without one
+    #[serde(serialize_with = "serialize_totals")]
+    pub totals: BTreeMap<Option<Currency>, CurrencyTotals>,
+    pub elapsed_seconds: f64,
+    pub transactions_per_second: f64,
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `t` towards its type, as accepted or rejected depending on `result`
+    pub fn record(&mut self, t: &Transaction, result: &Result<(), TransactionManagerError>) {
+        let type_stats = self.by_type.entry(t.type_name()).or_default();
+        type_stats.processed += 1;
+
+        match result {
+            Ok(()) => type_stats.accepted += 1,
+            Err(e) => {
+                type_stats.rejected += 1;
+                *type_stats.rejected_by_error.entry(e.code()).or_default() += 1;
+            }
+        }
+    }
+
+    /// Adds the amount of `t`, which has been applied and recorded in `history`, to the
+    /// totals
+    pub fn record_applied(&mut self, t: &Transaction, history: &TransactionHistory) {
+        let levied_fee = history.levied_fee(t.tx()).unwrap_or(0.0);
+
+        match t {
+            Transaction::Deposit(d) => {
+                let totals = self.totals.entry(d.currency).or_default();
+                totals.deposited += d.amount;
+                totals.fees += levied_fee;
+            }
+            Transaction::Withdrawal(w) => {
+                let totals = self.totals.entry(w.currency).or_default();
+                totals.withdrawn += w.amount;
+                totals.fees += levied_fee;
+            }
+            Transaction::Fee(f) => self.totals.entry(f.currency).or_default().fees += f.amount,
+            Transaction::Interest(i) => {
+                self.totals.entry(i.currency).or_default().interest += i.amount;
+            }
+            // The fee levied on a deposit is kept when it's reversed, while that levied on a
+            // withdrawal is refunded
+            Transaction::Reversal(r) => {
+                if let Some(deposit) = history.deposit(r.tx) {
+                    let totals = self
+                        .totals
+                        .entry(history.currency(deposit.currency))
+                        .or_default();
+                    totals.deposited -= deposit.amount;
+                } else if let Some(withdrawal) = history.withdrawal(r.tx) {
+                    let totals = self
+                        .totals
+                        .entry(history.currency(withdrawal.currency))
+                        .or_default();
+                    totals.withdrawn -= withdrawal.amount;
+                    totals.fees -= levied_fee;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Totals of funds in `currency`, which are all zero if nothing was moved in it
+    pub fn totals(&self, currency: Option<Currency>) -> CurrencyTotals {
+        self.totals.get(&currency).copied().unwrap_or_default()
+    }
+
+    pub fn processed(&self) -> usize {
+        self.by_type.values().map(|stats| stats.processed).sum()
+    }
+
+    pub fn with_parse_failures(mut self, parse_failures: usize) -> Self {
+        self.parse_failures = parse_failures;
+        self
+    }
+
+    /// Sets how long the run took, from which the throughput is worked out
+    pub fn with_elapsed(mut self, elapsed: Duration) -> Self {
+        self.elapsed_seconds = elapsed.as_secs_f64();
+        self.transactions_per_second = if self.elapsed_seconds > 0.0 {
+            (self.processed() + self.parse_failures) as f64 / self.elapsed_seconds
+        } else {
+            0.0
+        };
+        self
+    }
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Processed {} transactions:", self.processed())?;
+        for (type_name, stats) in &self.by_type {
+            write!(
+                f,
+                "  {type_name}: {} processed, {} accepted, {} rejected",
+                stats.processed, stats.accepted, stats.rejected
+            )?;
+            for (error, count) in &stats.rejected_by_error {
+                write!(f, ", {count} {error}")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "Parse failures: {}", self.parse_failures)?;
+        writeln!(
+            f,
+            "Clients: {}, of which locked: {}",
+            self.clients, self.locked_clients
+        )?;
+        for (currency, totals) in &self.totals {
+            if let Some(currency) = currency {
+                write!(f, "{currency}: ")?;
+            }
+            writeln!(
+                f,
+                "Deposited: {}, withdrawn: {}, fees: {}, interest: {}, held: {}, charged back: {}",
+                totals.deposited,
+                totals.withdrawn,
+                totals.fees,
+                totals.interest,
+                totals.held,
+                totals.charged_back
+            )?;
+        }
+        write!(
+            f,
+            "Took {:.3}s, {:.0} transactions per second",
+            self.elapsed_seconds, self.transactions_per_second
+        )
+    }
+}
+
+fn serialize_totals<S>(
+    totals: &BTreeMap<Option<Currency>, CurrencyTotals>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    #[derive(Serialize)]
+    struct Entry<'a> {
+        currency: Option<Currency>,
+        #[serde(flatten)]
+        totals: &'a CurrencyTotals,
+    }
+
+    serializer.collect_seq(totals.iter().map(|(currency, totals)| Entry {
+        currency: *currency,
+        totals,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{Deposit, Fee, Interest, Withdrawal};
+
+    #[test]
+    fn test_run_stats_per_type() {
+        let deposit = Transaction::Deposit(Deposit::new(1, 1, 10.0));
+        let withdrawal = Transaction::Withdrawal(Withdrawal::new(1, 2, 4.0));
+
+        let history = TransactionHistory::new();
+        let mut stats = RunStats::new();
+        stats.record(&deposit, &Ok(()));
+        stats.record_applied(&deposit, &history);
+        stats.record(&withdrawal, &Ok(()));
+        stats.record_applied(&withdrawal, &history);
+        stats.record(
+            &withdrawal,
+            &Err(TransactionManagerError::DuplicateTransactionId { client: 1, tx: 2 }),
+        );
+
+        let stats = stats
+            .with_parse_failures(1)
+            .with_elapsed(Duration::from_secs(2));
+
+        assert_eq!(stats.processed(), 3);
+        assert_eq!(stats.by_type["withdrawal"].rejected, 1);
+        assert_eq!(
+            stats.by_type["withdrawal"].rejected_by_error["duplicate_transaction_id"],
+            1
+        );
+        assert_eq!(stats.totals(None).deposited, 10.0);
+        assert_eq!(stats.totals(None).withdrawn, 4.0);
+        assert_eq!(stats.transactions_per_second, 2.0);
+    }
+
+    #[test]
+    fn test_run_stats_per_currency() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut history = TransactionHistory::new();
+        history.insert_levied_fee(2, 0.5);
+
+        let mut stats = RunStats::new();
+        for t in [
+            Transaction::Deposit(Deposit::new(1, 1, 10.0)),
+            Transaction::Deposit(Deposit::new(1, 2, 20.0).with_currency(eur)),
+            Transaction::Fee(Fee::new(1, 3, 1.5).with_currency(eur)),
+            Transaction::Interest(Interest::new(1, 4, 0.25)),
+        ] {
+            stats.record_applied(&t, &history);
+        }
+
+        assert_eq!(
+            stats.totals(None),
+            CurrencyTotals {
+                deposited: 10.0,
+                interest: 0.25,
+                ..CurrencyTotals::default()
+            }
+        );
+        assert_eq!(
+            stats.totals(Some(eur)),
+            CurrencyTotals {
+                deposited: 20.0,
+                fees: 2.0,
+                ..CurrencyTotals::default()
+            }
+        );
+
+        assert_eq!(
+            stats.to_string().lines().nth(3).unwrap(),
+            "Deposited: 10, withdrawn: 0, fees: 0, interest: 0.25, held: 0, charged back: 0"
+        );
+        assert_eq!(
+            stats.to_string().lines().nth(4).unwrap(),
+            "EUR: Deposited: 20, withdrawn: 0, fees: 2, interest: 0, held: 0, charged back: 0"
+        );
+    }
+}
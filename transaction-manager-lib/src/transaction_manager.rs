@@ -1,44 +1,335 @@
 use crate::balance::ClientBalanceRegistry;
-use crate::history::TransactionHistory;
-use crate::transactions::{Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal};
-use log::*;
+use crate::checkpoints::{AsOf, Change, Checkpoints, ManagerState};
+use crate::currency::{Currency, RateProvider};
+use crate::fees::FeeSchedule;
+use crate::history::{DepositRecord, TransactionHistory, TransactionState, WithdrawalRecord};
+use crate::idempotency::IdempotencyIndex;
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::policy::{DisputePolicy, NegativeBalancePolicy};
+use crate::rules::{RuleAction, RuleMatch, RulesEngine};
+use crate::simulation::{Rejection, Simulation};
+use crate::stats::RunStats;
+use crate::transactions::{
+    Approve, Chargeback, Decline, Deposit, Dispute, Exchange, Fee, Interest, OverdraftLimit,
+    Resolve, Reversal, Transaction, Transfer, Withdrawal,
+};
+use crate::validators::{LedgerView, TransactionValidator};
 use std::clone::Clone;
 use std::fmt;
 use std::sync::{Arc, RwLock};
-
+use tracing::{debug, field, info_span, trace, warn};
+
+// Partial amounts are summed and subtracted as floats, so allow for rounding errors when
+// comparing them
+const AMOUNT_TOLERANCE: f64 = 1e-9;
+
+type Validation = fn(&TransactionManager, &Transaction) -> Result<(), TransactionManagerError>;
+
+// Checks every transaction goes through before being handed to the handler for its type,
+// in this order. Anything only to do with a single type is left to its handler
+const VALIDATIONS: &[Validation] = &[
+    TransactionManager::reject_duplicate,
+    TransactionManager::reject_negative_amount,
+    TransactionManager::reject_locked_accounts,
+    TransactionManager::reject_unowned,
+];
+
+/// Why a transaction was rejected. Every variant names the client and transaction it's
+/// about, where `tx` is the id the rejected row gave, e.g. the disputed deposit's for a
+/// dispute.
 #[derive(Debug, PartialEq)]
 pub enum TransactionManagerError {
-    // TODO: Consider if we want something more sophisticated
-    InvalidTransaction(String),
-    InsufficientFunds(f64),
-    AccountLocked,
-    DuplicateTransactionId(u32),
-    DisputedTransactionDoesNotExist(u32),
-    NoOpenDispute(u32),
-    NegativeAmountNotAllowed,
+    InvalidTransaction {
+        client: u16,
+        tx: u32,
+        reason: String,
+    },
+    /// `available` is what the client could take out, including any overdraft
+    InsufficientFunds {
+        client: u16,
+        tx: u32,
+        requested: f64,
+        available: f64,
+    },
+    AccountLocked {
+        client: u16,
+        tx: u32,
+    },
+    DuplicateTransactionId {
+        client: u16,
+        tx: u32,
+    },
+    DisputedTransactionDoesNotExist {
+        client: u16,
+        tx: u32,
+    },
+    NoOpenDispute {
+        client: u16,
+        tx: u32,
+    },
+    TransactionAlreadyDisputed {
+        client: u16,
+        tx: u32,
+    },
+    TransactionNotOwnedByClient {
+        client: u16,
+        tx: u32,
+        owner: u16,
+    },
+    DisputedAmountExceedsUndisputed {
+        client: u16,
+        tx: u32,
+        requested: f64,
+        undisputed: f64,
+    },
+    AmountExceedsHeld {
+        client: u16,
+        tx: u32,
+        requested: f64,
+        held: f64,
+    },
+    DisputeWindowExpired {
+        client: u16,
+        tx: u32,
+        deposited_at: u64,
+        disputed_at: u64,
+    },
+    NoExchangeRate {
+        client: u16,
+        tx: u32,
+        from: Currency,
+        to: Currency,
+    },
+    /// `available` is what the client could have held, including any overdraft
+    DisputeWouldOverdraw {
+        client: u16,
+        tx: u32,
+        requested: f64,
+        available: f64,
+    },
+    NoFundsAvailableToHold {
+        client: u16,
+        tx: u32,
+    },
+    NegativeAmountNotAllowed {
+        client: u16,
+        tx: u32,
+        amount: f64,
+    },
+    /// Only given by `TransactionManager::set_overdraft_limit`, which has no transaction
+    NegativeOverdraftLimit {
+        client: u16,
+        limit: f64,
+    },
+    RejectedByRule {
+        client: u16,
+        tx: u32,
+        rule: String,
+    },
+    HeldForReview {
+        client: u16,
+        tx: u32,
+        rule: String,
+    },
+    RejectedByValidator {
+        client: u16,
+        tx: u32,
+        validator: String,
+        reason: String,
+    },
+    NoPendingTransaction {
+        client: u16,
+        tx: u32,
+    },
+    TransactionNotReversible {
+        client: u16,
+        tx: u32,
+    },
+    AlreadyReversed {
+        client: u16,
+        tx: u32,
+    },
+    ReversalOfDisputedTransaction {
+        client: u16,
+        tx: u32,
+    },
+    AlreadyApplied {
+        client: u16,
+        tx: u32,
+    },
+    IdempotencyIndexWriteFailed {
+        client: u16,
+        tx: u32,
+        reason: String,
+    },
 }
 
 impl fmt::Display for TransactionManagerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] ", self.code())?;
+
+        match self {
+            TransactionManagerError::InvalidTransaction { client, tx, reason } => {
+                write!(f, "Invalid tx {tx} of client {client}: {reason}")
+            }
+            TransactionManagerError::InsufficientFunds {
+                client,
+                tx,
+                requested,
+                available,
+            } => write!(
+                f,
+                "Insufficient funds for tx {tx} of client {client}: requested {requested}, available {available}"
+            ),
+            TransactionManagerError::AccountLocked { client, tx } => {
+                write!(f, "Account of client {client} is locked, rejecting tx {tx}")
+            }
+            TransactionManagerError::DuplicateTransactionId { client, tx } => {
+                write!(f, "Duplicate tx {tx} from client {client}")
+            }
+            TransactionManagerError::DisputedTransactionDoesNotExist { client, tx } => {
+                write!(f, "Client {client} referred to tx {tx}, which can't be disputed or doesn't exist")
+            }
+            TransactionManagerError::NoOpenDispute { client, tx } => {
+                write!(f, "No open dispute of tx {tx} for client {client}")
+            }
+            TransactionManagerError::TransactionAlreadyDisputed { client, tx } => {
+                write!(f, "Tx {tx} of client {client} is already fully disputed")
+            }
+            TransactionManagerError::TransactionNotOwnedByClient { client, tx, owner } => {
+                write!(f, "Tx {tx} belongs to client {owner}, not client {client}")
+            }
+            TransactionManagerError::DisputedAmountExceedsUndisputed {
+                client,
+                tx,
+                requested,
+                undisputed,
+            } => write!(
+                f,
+                "Client {client} disputed {requested} of tx {tx}, but only {undisputed} is undisputed"
+            ),
+            TransactionManagerError::AmountExceedsHeld {
+                client,
+                tx,
+                requested,
+                held,
+            } => write!(
+                f,
+                "Client {client} asked for {requested} of tx {tx}, but only {held} is held"
+            ),
+            TransactionManagerError::DisputeWindowExpired {
+                client,
+                tx,
+                deposited_at,
+                disputed_at,
+            } => write!(
+                f,
+                "Client {client} disputed tx {tx} at {disputed_at}, too long after it was made at {deposited_at}"
+            ),
+            TransactionManagerError::NoExchangeRate { client, tx, from, to } => {
+                write!(f, "No rate from {from} to {to} for tx {tx} of client {client}")
+            }
+            TransactionManagerError::DisputeWouldOverdraw {
+                client,
+                tx,
+                requested,
+                available,
+            } => write!(
+                f,
+                "Dispute of tx {tx} would hold {requested} of client {client}, but only {available} is available"
+            ),
+            TransactionManagerError::NoFundsAvailableToHold { client, tx } => {
+                write!(f, "Client {client} has no funds available to hold for a dispute of tx {tx}")
+            }
+            TransactionManagerError::NegativeAmountNotAllowed { client, tx, amount } => {
+                write!(f, "Negative amount {amount} in tx {tx} of client {client}")
+            }
+            TransactionManagerError::NegativeOverdraftLimit { client, limit } => {
+                write!(f, "Negative overdraft limit {limit} for client {client}")
+            }
+            TransactionManagerError::RejectedByRule { client, tx, rule } => {
+                write!(f, "Tx {tx} of client {client} rejected by rule {rule}")
+            }
+            TransactionManagerError::HeldForReview { client, tx, rule } => {
+                write!(f, "Tx {tx} of client {client} held for review by rule {rule}")
+            }
+            TransactionManagerError::RejectedByValidator {
+                client,
+                tx,
+                validator,
+                reason,
+            } => write!(
+                f,
+                "Tx {tx} of client {client} rejected by validator {validator}: {reason}"
+            ),
+            TransactionManagerError::NoPendingTransaction { client, tx } => {
+                write!(f, "No tx {tx} of client {client} is pending review")
+            }
+            TransactionManagerError::TransactionNotReversible { client, tx } => {
+                write!(f, "Tx {tx} isn't a deposit or withdrawal client {client} can reverse")
+            }
+            TransactionManagerError::AlreadyReversed { client, tx } => {
+                write!(f, "Tx {tx} of client {client} has already been reversed")
+            }
+            TransactionManagerError::ReversalOfDisputedTransaction { client, tx } => {
+                write!(f, "Tx {tx} of client {client} has been disputed, so can't be reversed")
+            }
+            TransactionManagerError::AlreadyApplied { client, tx } => {
+                write!(f, "This row of tx {tx} of client {client} was already recorded")
+            }
+            TransactionManagerError::IdempotencyIndexWriteFailed { client, tx, reason } => {
+                write!(f, "Tx {tx} of client {client} was applied, but couldn't be recorded in the idempotency index: {reason}")
+            }
+        }
+    }
+}
+
+impl TransactionManagerError {
+    /// Stable, machine readable code for the kind of error, e.g. `insufficient_funds`.
+    /// Unlike the message, codes are never changed once given out
+    pub fn code(&self) -> &'static str {
         match self {
-            TransactionManagerError::InvalidTransaction(reason) => {
-                write!(f, "InvalidTransaction: {reason}")
+            TransactionManagerError::InvalidTransaction { .. } => "invalid_transaction",
+            TransactionManagerError::InsufficientFunds { .. } => "insufficient_funds",
+            TransactionManagerError::AccountLocked { .. } => "account_locked",
+            TransactionManagerError::DuplicateTransactionId { .. } => "duplicate_transaction_id",
+            TransactionManagerError::DisputedTransactionDoesNotExist { .. } => {
+                "disputed_transaction_does_not_exist"
+            }
+            TransactionManagerError::NoOpenDispute { .. } => "no_open_dispute",
+            TransactionManagerError::TransactionAlreadyDisputed { .. } => {
+                "transaction_already_disputed"
+            }
+            TransactionManagerError::TransactionNotOwnedByClient { .. } => {
+                "transaction_not_owned_by_client"
             }
-            TransactionManagerError::InsufficientFunds(insufficient_amount) => {
-                write!(f, "InsufficientFunds({insufficient_amount}")
+            TransactionManagerError::DisputedAmountExceedsUndisputed { .. } => {
+                "disputed_amount_exceeds_undisputed"
             }
-            TransactionManagerError::AccountLocked => write!(f, "AccountLocked"),
-            TransactionManagerError::DuplicateTransactionId(duped_id) => {
-                write!(f, "DuplicateTransactionId({duped_id}")
+            TransactionManagerError::AmountExceedsHeld { .. } => "amount_exceeds_held",
+            TransactionManagerError::DisputeWindowExpired { .. } => "dispute_window_expired",
+            TransactionManagerError::NoExchangeRate { .. } => "no_exchange_rate",
+            TransactionManagerError::DisputeWouldOverdraw { .. } => "dispute_would_overdraw",
+            TransactionManagerError::NoFundsAvailableToHold { .. } => "no_funds_available_to_hold",
+            TransactionManagerError::NegativeAmountNotAllowed { .. } => {
+                "negative_amount_not_allowed"
             }
-            TransactionManagerError::DisputedTransactionDoesNotExist(tx) => {
-                write!(f, "DisputedTransactionDoesNotExist({tx})")
+            TransactionManagerError::NegativeOverdraftLimit { .. } => "negative_overdraft_limit",
+            TransactionManagerError::RejectedByRule { .. } => "rejected_by_rule",
+            TransactionManagerError::HeldForReview { .. } => "held_for_review",
+            TransactionManagerError::RejectedByValidator { .. } => "rejected_by_validator",
+            TransactionManagerError::NoPendingTransaction { .. } => "no_pending_transaction",
+            TransactionManagerError::TransactionNotReversible { .. } => {
+                "transaction_not_reversible"
             }
-            TransactionManagerError::NoOpenDispute(tx) => {
-                write!(f, "NoOpenDispute({tx})")
+            TransactionManagerError::AlreadyReversed { .. } => "already_reversed",
+            TransactionManagerError::ReversalOfDisputedTransaction { .. } => {
+                "reversal_of_disputed_transaction"
             }
-            TransactionManagerError::NegativeAmountNotAllowed => {
-                write!(f, "NegativeAmountNotAllowed")
+            TransactionManagerError::AlreadyApplied { .. } => "already_applied",
+            TransactionManagerError::IdempotencyIndexWriteFailed { .. } => {
+                "idempotency_index_write_failed"
             }
         }
     }
@@ -52,6 +343,26 @@ pub struct TransactionManager {
     // (although, there's probably a better way to do that still!)
     balances: Arc<RwLock<ClientBalanceRegistry>>,
     history: TransactionHistory,
+    idempotency_index: Option<IdempotencyIndex>,
+    dispute_policy: DisputePolicy,
+    negative_balance_policy: NegativeBalancePolicy,
+    fee_schedule: FeeSchedule,
+    rate_provider: Option<Arc<dyn RateProvider>>,
+    rules: Option<RulesEngine>,
+    // Run in the order they were added, after the manager's own validations
+    validators: Vec<Arc<dyn TransactionValidator>>,
+    // Every rule matched so far, whatever its action
+    rule_matches: Vec<RuleMatch>,
+    stats: RunStats,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<Metrics>>,
+    // Latest timestamp of a transaction which passed validation, if any
+    now: Option<u64>,
+    checkpoints: Option<Checkpoints>,
+    // Kept up to date as disputes are opened and closed and accounts locked, so that the
+    // metrics don't have to go over every account
+    open_disputes: usize,
+    locked_accounts: usize,
 }
 
 impl TransactionManager {
@@ -59,23 +370,414 @@ impl TransactionManager {
         Self {
             balances: Arc::new(RwLock::new(ClientBalanceRegistry::new())),
             history: TransactionHistory::new(),
+            idempotency_index: None,
+            dispute_policy: DisputePolicy::default(),
+            negative_balance_policy: NegativeBalancePolicy::default(),
+            fee_schedule: FeeSchedule::default(),
+            rate_provider: None,
+            rules: None,
+            validators: Vec::new(),
+            rule_matches: Vec::new(),
+            stats: RunStats::new(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            now: None,
+            checkpoints: None,
+            open_disputes: 0,
+            locked_accounts: 0,
+        }
+    }
+
+    /// Source of the rates exchanges are made at. Without one, exchanges are rejected
+    pub fn with_rate_provider<R: RateProvider + 'static>(mut self, provider: R) -> Self {
+        self.rate_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Adds a check every transaction has to pass, after the manager's own and any
+    /// validators added before it
+    pub fn with_validator<V: TransactionValidator + 'static>(mut self, validator: V) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    pub fn with_fee_schedule(mut self, schedule: FeeSchedule) -> Self {
+        self.fee_schedule = schedule;
+        self
+    }
+
+    pub fn with_dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.dispute_policy = policy;
+        self
+    }
+
+    pub fn with_negative_balance_policy(mut self, policy: NegativeBalancePolicy) -> Self {
+        self.negative_balance_policy = policy;
+        self
+    }
+
+    /// Rules checked against every transaction before it's applied
+    pub fn with_rules(mut self, rules: RulesEngine) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
+    /// Metrics fed with every recorded transaction
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Moves the clock forward to `now`, resolving any dispute which has been open for
+    /// longer than the `DisputePolicy` allows. Time never moves backwards.
+    pub fn advance_time(&mut self, now: u64) {
+        self.note_change(Change::ClockMoved(now));
+        self.move_clock(now);
+    }
+
+    fn move_clock(&mut self, now: u64) {
+        if self.now.is_some_and(|current| current >= now) {
+            return;
+        }
+        self.now = Some(now);
+
+        let Some(cutoff) = self.dispute_policy.expiry_cutoff(now) else {
+            return;
+        };
+
+        let mut registry = self.balances.write().unwrap();
+
+        for tx in self.history.disputes_opened_by(cutoff) {
+            let currency = self
+                .history
+                .deposit(tx)
+                .and_then(|dep| self.history.currency(dep.currency));
+
+            let Some((dep, disputed)) = self.history.disputed_deposit_mut(tx) else {
+                continue;
+            };
+
+            let client_account = registry.client_balances.entry(dep.client).or_default();
+
+            // Same as for a manual resolve, locked accounts are left alone
+            if client_account.locked {
+                continue;
+            }
+
+            debug!(tx, "Dispute expired, resolving it");
+
+            let funds = client_account.funds.entry(currency).or_default();
+            funds.available += disputed.held;
+            funds.held -= disputed.held;
+            if client_account.disputed_transactions.remove(&tx) {
+                self.open_disputes -= 1;
+            }
+
+            disputed.held = 0.0;
+            dep.state = if disputed.charged_back > 0.0 {
+                TransactionState::ChargedBack
+            } else {
+                TransactionState::Settled
+            };
+            self.history.close_dispute(tx);
         }
     }
 
+    /// Keeps every transaction recorded, along with copies of the state at least
+    /// `interval` of them apart, so that the balances at any earlier point can be rebuilt
+    /// with `balances_as_of`. Copies are taken further apart as the run goes on, so that
+    /// together they take at most about twice the memory of the balances and history, on
+    /// top of the copy of every transaction.
+    pub fn with_checkpoints(mut self, interval: usize) -> Self {
+        self.checkpoints = Some(Checkpoints::new(interval));
+        self
+    }
+
+    /// Skips any transaction already present in `index` and records every newly
+    /// applied transaction into it
+    pub fn with_idempotency_index(mut self, index: IdempotencyIndex) -> Self {
+        self.idempotency_index = Some(index);
+        self
+    }
+
     pub fn record_transaction(&mut self, t: &Transaction) -> Result<(), TransactionManagerError> {
-        debug!("Transaction: {t:?}");
+        // Everything logged while recording the transaction is within its span, so that
+        // it can be told which transaction it was about
+        let span = info_span!(
+            "transaction",
+            tx = t.tx(),
+            client = t.client(),
+            r#type = t.type_name(),
+            outcome = field::Empty,
+        );
+        let _entered = span.enter();
+        trace!(transaction = ?t);
+
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+
+        self.take_initial_checkpoint();
+
+        let result = self.process(t);
+        let result = self.record_in_index(t, result);
+        self.stats.record(t, &result);
+
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.record(t, self.now);
+            if checkpoints.is_due() {
+                self.checkpoint();
+            }
+        }
+
+        match &result {
+            Ok(()) => {
+                span.record("outcome", "accepted");
+                debug!("Transaction accepted");
+            }
+            Err(e) => {
+                span.record("outcome", e.code());
+                debug!(error = %e, "Transaction rejected");
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        self.update_metrics(t, &result, started.elapsed());
+
+        result
+    }
+
+    /// What recording `transactions`, in order, would do, without changing anything.
+    ///
+    /// They're applied to a copy of only the entries of the balances, history, rules and
+    /// idempotency index which they can read or change, along with the open disputes and
+    /// the transactions held for review, so a simulation takes memory and time in
+    /// proportion to how many transactions are simulated rather than to the size of the
+    /// manager. Validators are given that copy too, so only see those entries. The copy
+    /// shares the rate provider and validators with this manager. Nothing is written to
+    /// the idempotency index, and neither the statistics nor the metrics count them.
+    pub fn simulate(&self, transactions: &[Transaction]) -> Simulation {
+        let _entered = info_span!("simulation").entered();
+
+        let state = self.state_for(transactions);
+        let before = state.balances.clone();
+        let mut scratch = self.resume(state);
+
+        let rejections = transactions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, t)| {
+                scratch
+                    .record_transaction(t)
+                    .err()
+                    .map(|error| Rejection { index, error })
+            })
+            .collect();
+
+        Simulation::new(&before, &scratch.retrieve_client_balances(), rejections)
+    }
+
+    /// Balances as they were at an earlier point of the run, or `None` if checkpoints
+    /// weren't enabled with `with_checkpoints` or the point hasn't been reached yet
+    pub fn balances_as_of(&self, as_of: AsOf) -> Option<ClientBalanceRegistry> {
+        let checkpoints = self.checkpoints.as_ref()?;
+
+        let sequence = match as_of {
+            AsOf::Sequence(sequence) => sequence,
+            AsOf::Timestamp(timestamp) => checkpoints.sequence_at(timestamp),
+        };
+        if sequence > checkpoints.len() {
+            return None;
+        }
+
+        // Nothing has been recorded yet, so nothing has changed
+        let Some((state, since)) = checkpoints.replay_from(sequence) else {
+            return Some(self.retrieve_client_balances());
+        };
+
+        let _entered = info_span!("replay", sequence).entered();
+        let mut scratch = self.resume(state.copy());
+        for change in since {
+            match change {
+                // Whatever happens was already reported when it was first recorded
+                Change::Recorded(t) => {
+                    let _ = scratch.record_transaction(t);
+                }
+                Change::ClockMoved(now) => scratch.move_clock(*now),
+                Change::OverdraftLimitSet { client, limit } => {
+                    scratch.apply_overdraft_limit(*client, *limit);
+                }
+            }
+        }
+
+        Some(scratch.retrieve_client_balances())
+    }
+
+    fn state(&self) -> ManagerState {
+        ManagerState {
+            balances: self.retrieve_client_balances(),
+            history: self.history.clone(),
+            idempotency_index: self
+                .idempotency_index
+                .as_ref()
+                .map(IdempotencyIndex::in_memory_copy),
+            rules: self.rules.clone(),
+            now: self.now,
+        }
+    }
+
+    // Copy of only the entries of the state which `transactions` can read or change
+    fn state_for(&self, transactions: &[Transaction]) -> ManagerState {
+        let txs = transactions.iter().map(Transaction::tx).collect();
+        let history = self.history.subset(&txs);
+
+        let mut clients = history.clients();
+        for t in transactions {
+            clients.insert(t.client());
+            clients.extend(t.counterparty());
+        }
+
+        ManagerState {
+            balances: self.balances.read().unwrap().subset(&clients),
+            history,
+            idempotency_index: self
+                .idempotency_index
+                .as_ref()
+                .map(|index| index.subset(transactions)),
+            rules: self.rules.as_ref().map(|rules| rules.subset(&clients)),
+            now: self.now,
+        }
+    }
 
-        // TODO: I'd like to reject duplicate transactions here, but because of how I currently
-        // have the id setup as a part of each individual transaction type, I cannot
-        // So I'll have to have some duplicate code in each of the below methods unfortunately
+    // A manager carrying on from `state` with the same policies, rate provider and
+    // validators as this one, but without statistics, metrics or checkpoints of its own
+    fn resume(&self, state: ManagerState) -> TransactionManager {
+        let open_disputes = state
+            .balances
+            .client_balances
+            .values()
+            .map(|balance| balance.disputed_transactions.len())
+            .sum();
+        let locked_accounts = state
+            .balances
+            .client_balances
+            .values()
+            .filter(|balance| balance.locked)
+            .count();
+
+        TransactionManager {
+            balances: Arc::new(RwLock::new(state.balances)),
+            history: state.history,
+            idempotency_index: state.idempotency_index,
+            dispute_policy: self.dispute_policy,
+            negative_balance_policy: self.negative_balance_policy,
+            fee_schedule: self.fee_schedule,
+            rate_provider: self.rate_provider.clone(),
+            rules: state.rules,
+            validators: self.validators.clone(),
+            rule_matches: Vec::new(),
+            stats: RunStats::new(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            now: state.now,
+            checkpoints: None,
+            open_disputes,
+            locked_accounts,
+        }
+    }
+
+    // Copies the state if checkpoints are enabled
+    fn checkpoint(&mut self) {
+        if self.checkpoints.is_none() {
+            return;
+        }
+
+        let state = self.state();
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.insert(state);
+        }
+    }
+
+    // Copies the state before the first change of all, which every replay starts from
+    fn take_initial_checkpoint(&mut self) {
+        if self
+            .checkpoints
+            .as_ref()
+            .is_some_and(Checkpoints::needs_initial_state)
+        {
+            self.checkpoint();
+        }
+    }
+
+    // Notes a change made other than by recording a transaction, which has to be made
+    // again when replaying, before it's made
+    fn note_change(&mut self, change: Change) {
+        self.take_initial_checkpoint();
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.note(change);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    fn update_metrics(
+        &self,
+        t: &Transaction,
+        result: &Result<(), TransactionManagerError>,
+        latency: std::time::Duration,
+    ) {
+        let Some(metrics) = self.metrics.as_ref() else {
+            return;
+        };
+
+        let outcome = match result {
+            Ok(()) => "accepted",
+            Err(e) => e.code(),
+        };
+        metrics.record_transaction(t.type_name(), outcome, latency);
+        metrics.set_history_size(self.history.len());
+        metrics.set_disputes(self.open_disputes, self.locked_accounts);
+    }
+
+    fn process(&mut self, t: &Transaction) -> Result<(), TransactionManagerError> {
+        self.already_applied(t)?;
+        self.validate(t)?;
+
+        // Only once it's known to be valid, so that e.g. a duplicate with a late timestamp
+        // can't expire anyone's disputes
+        if let Some(timestamp) = t.timestamp() {
+            self.move_clock(timestamp);
+        }
+
+        self.check_rules(t)?;
+
+        self.apply(t)
+    }
 
+    // Applies `t`, which has been validated, without checking it against the rules. Which
+    // is also how a transaction held for review is applied once it's approved
+    fn apply(&mut self, t: &Transaction) -> Result<(), TransactionManagerError> {
         match t {
             Transaction::Withdrawal(w) => self.handle_withdrawal(w),
             Transaction::Deposit(d) => self.handle_deposit(d),
+            Transaction::Transfer(tr) => self.handle_transfer(tr),
+            Transaction::Fee(f) => self.handle_fee(f),
+            Transaction::Interest(i) => self.handle_interest(i),
+            Transaction::Exchange(e) => self.handle_exchange(e),
+            Transaction::OverdraftLimit(o) => self.handle_overdraft_limit(o),
             Transaction::Chargeback(c) => self.handle_chargeback(c),
             Transaction::Resolve(r) => self.handle_resolve(r),
             Transaction::Dispute(d) => self.handle_dispute(d),
+            Transaction::Reversal(r) => self.handle_reversal(r),
+            Transaction::Approve(a) => self.handle_approve(a),
+            Transaction::Decline(d) => self.handle_decline(d),
+        }?;
+
+        if let Some(rules) = self.rules.as_mut() {
+            rules.observe(t);
         }
+        self.stats.record_applied(t, &self.history);
+
+        Ok(())
     }
 
     pub fn retrieve_client_balances(&self) -> ClientBalanceRegistry {
@@ -84,261 +786,2365 @@ impl TransactionManager {
         (*balance).clone()
     }
 
-    fn duped_transaction(&self, tx: &u32) -> Result<(), TransactionManagerError> {
-        if self.history.get(tx).is_some() {
-            return Err(TransactionManagerError::DuplicateTransactionId(*tx));
+    /// Lets the client's available funds go as far as `limit` below zero, see `ClientBalance`
+    pub fn set_overdraft_limit(
+        &mut self,
+        client: u16,
+        limit: f64,
+    ) -> Result<(), TransactionManagerError> {
+        if limit < 0.0 {
+            return Err(TransactionManagerError::NegativeOverdraftLimit { client, limit });
         }
 
+        self.note_change(Change::OverdraftLimitSet { client, limit });
+        self.apply_overdraft_limit(client, limit);
+
         Ok(())
     }
 
-    fn reject_negative_amount(&self, amount: &f64) -> Result<(), TransactionManagerError> {
-        if *amount < 0.0 {
-            return Err(TransactionManagerError::NegativeAmountNotAllowed);
+    fn apply_overdraft_limit(&mut self, client: u16, limit: f64) {
+        let mut registry = self.balances.write().unwrap();
+
+        let client_account = registry.client_balances.entry(client).or_default();
+        client_account.overdraft_limit = limit;
+
+        trace!(?client_account, "after");
+    }
+
+    /// Every rule matched so far, including those of transactions which were then
+    /// rejected or held
+    pub fn rule_matches(&self) -> &[RuleMatch] {
+        &self.rule_matches
+    }
+
+    /// Statistics of every transaction recorded so far, along with the current number of
+    /// clients and amount held
+    pub fn run_stats(&self) -> RunStats {
+        let registry = self.balances.read().unwrap();
+
+        let mut stats = self.stats.clone();
+        stats.clients = registry.client_balances.len();
+        stats.locked_clients = registry
+            .client_balances
+            .values()
+            .filter(|balance| balance.locked)
+            .count();
+        for (currency, funds) in registry
+            .client_balances
+            .values()
+            .flat_map(|balance| &balance.funds)
+        {
+            stats.totals.entry(*currency).or_default().held += funds.held;
+        }
+        for (currency, charged_back) in self.history.charged_back_by_currency() {
+            stats.totals.entry(currency).or_default().charged_back += charged_back;
         }
 
-        Ok(())
+        stats
     }
 
-    fn handle_withdrawal(&mut self, w: &Withdrawal) -> Result<(), TransactionManagerError> {
-        debug!("{w:?}");
+    pub fn history(&self) -> &TransactionHistory {
+        &self.history
+    }
+
+    // Notes what became of `t` in the idempotency index, unless it was skipped for being
+    // there already
+    fn record_in_index(
+        &mut self,
+        t: &Transaction,
+        result: Result<(), TransactionManagerError>,
+    ) -> Result<(), TransactionManagerError> {
+        let Some(index) = self.idempotency_index.as_mut() else {
+            return result;
+        };
 
-        self.duped_transaction(&w.tx)?;
-        self.reject_negative_amount(&w.amount)?;
+        let outcome = match &result {
+            Ok(()) => "accepted",
+            Err(TransactionManagerError::AlreadyApplied { .. }) => return result,
+            Err(e) => e.code(),
+        };
 
-        let mut registry = self.balances.write().unwrap();
+        // The transaction has already been processed at this point, so a failure here only
+        // means that a replay of it wouldn't be recognized
+        match (index.record(t, outcome), result) {
+            (Ok(()), result) => result,
+            (Err(e), Ok(())) => Err(TransactionManagerError::IdempotencyIndexWriteFailed {
+                client: t.client(),
+                tx: t.tx(),
+                reason: e.to_string(),
+            }),
+            (Err(e), Err(rejection)) => {
+                warn!(error = %e, "Failed to record the rejection in the idempotency index");
+                Err(rejection)
+            }
+        }
+    }
 
-        let client_account = registry.client_balances.entry(w.client).or_default();
-        trace!("client_account, prior: {client_account:?}");
+    fn already_applied(&self, t: &Transaction) -> Result<(), TransactionManagerError> {
+        let Some(index) = self.idempotency_index.as_ref() else {
+            return Ok(());
+        };
 
-        if client_account.locked {
-            return Err(TransactionManagerError::AccountLocked);
+        if index.contains(t) {
+            return Err(TransactionManagerError::AlreadyApplied {
+                client: t.client(),
+                tx: t.tx(),
+            });
         }
 
-        let remaining_amount = client_account.available - w.amount;
+        Ok(())
+    }
 
-        if remaining_amount < 0.0 {
-            return Err(TransactionManagerError::InsufficientFunds(
-                remaining_amount * -1.0,
-            ));
+    // Applies the most severe action of any rule `t` matches
+    fn check_rules(&mut self, t: &Transaction) -> Result<(), TransactionManagerError> {
+        let Some(rules) = self.rules.as_ref() else {
+            return Ok(());
+        };
+
+        let matches = rules.evaluate(t);
+        let strictest = matches
+            .iter()
+            .max_by_key(|matched| matched.action)
+            .map(|matched| (matched.action, matched.condition.to_string()));
+
+        for matched in &matches {
+            warn!(
+                client = matched.client,
+                tx = matched.tx,
+                condition = %matched.condition,
+                action = ?matched.action,
+                "Rule matched"
+            );
+        }
+        self.rule_matches.extend(matches);
+
+        match strictest {
+            Some((RuleAction::Reject, rule)) => Err(TransactionManagerError::RejectedByRule {
+                client: t.client(),
+                tx: t.tx(),
+                rule,
+            }),
+            Some((RuleAction::Hold, rule)) => {
+                self.history.insert_pending(t.clone());
+                Err(TransactionManagerError::HeldForReview {
+                    client: t.client(),
+                    tx: t.tx(),
+                    rule,
+                })
+            }
+            Some((RuleAction::Alert, _)) | None => Ok(()),
         }
+    }
 
-        client_account.total -= w.amount;
-        client_account.available -= w.amount;
+    fn validate(&self, t: &Transaction) -> Result<(), TransactionManagerError> {
+        VALIDATIONS
+            .iter()
+            .try_for_each(|validation| validation(self, t))?;
 
-        trace!("client_account, after: {client_account:?}");
+        if self.validators.is_empty() {
+            return Ok(());
+        }
 
-        self.history
-            .insert(w.tx, Transaction::Withdrawal(w.clone()));
+        let registry = self.balances.read().unwrap();
+        let ledger = LedgerView {
+            balance: registry.client_balances.get(&t.client()),
+            history: &self.history,
+        };
 
-        trace!("history: {:?}", self.history);
+        for validator in &self.validators {
+            validator.validate(t, &ledger).map_err(|reason| {
+                TransactionManagerError::RejectedByValidator {
+                    client: t.client(),
+                    tx: t.tx(),
+                    validator: validator.name().to_string(),
+                    reason,
+                }
+            })?;
+        }
 
         Ok(())
     }
 
-    fn handle_deposit(&mut self, d: &Deposit) -> Result<(), TransactionManagerError> {
-        debug!("{d:?}");
+    fn reject_duplicate(&self, t: &Transaction) -> Result<(), TransactionManagerError> {
+        if !t.kind().refers_to_earlier() && self.history.contains(t.tx()) {
+            return Err(TransactionManagerError::DuplicateTransactionId {
+                client: t.client(),
+                tx: t.tx(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn reject_negative_amount(&self, t: &Transaction) -> Result<(), TransactionManagerError> {
+        match t.amount() {
+            Some(amount) if amount < 0.0 => {
+                Err(TransactionManagerError::NegativeAmountNotAllowed {
+                    client: t.client(),
+                    tx: t.tx(),
+                    amount,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // TODO: It would appear that there's not a description of what operations should
+    // be allowed if an account is locked / frozen (as far as I can tell).
+    // => ASSUMPTION: Locked accounts can have not operations performed on them,
+    //                perhaps they need some sort of manual intervention
+    fn reject_locked_accounts(&self, t: &Transaction) -> Result<(), TransactionManagerError> {
+        if t.kind().is_administrative() {
+            return Ok(());
+        }
+
+        let registry = self.balances.read().unwrap();
+
+        // A transfer needs both accounts open, and if neither is it's the sender's which
+        // is reported
+        let locked = std::iter::once(t.client())
+            .chain(t.counterparty())
+            .find(|client| {
+                registry
+                    .client_balances
+                    .get(client)
+                    .is_some_and(|account| account.locked)
+            });
+
+        match locked {
+            Some(client) => Err(TransactionManagerError::AccountLocked { client, tx: t.tx() }),
+            None => Ok(()),
+        }
+    }
+
+    // Only the client who was credited can dispute a deposit, which for a transfer is the
+    // recipient, only the client who made a deposit or withdrawal can have it reversed, and
+    // only the client who made a held transaction can have it approved or declined
+    fn reject_unowned(&self, t: &Transaction) -> Result<(), TransactionManagerError> {
+        let owner = match t {
+            Transaction::Dispute(_) | Transaction::Resolve(_) | Transaction::Chargeback(_) => {
+                self.history.deposit(t.tx()).map(|dep| dep.client)
+            }
+            Transaction::Reversal(_) => self
+                .history
+                .deposit(t.tx())
+                .map(|dep| dep.client)
+                .or_else(|| self.history.withdrawal(t.tx()).map(|w| w.client)),
+            Transaction::Approve(_) | Transaction::Decline(_) => self
+                .history
+                .pending_transaction(t.tx())
+                .map(Transaction::client),
+            _ => None,
+        };
+
+        match owner {
+            Some(owner) if owner != t.client() => {
+                Err(TransactionManagerError::TransactionNotOwnedByClient {
+                    client: t.client(),
+                    tx: t.tx(),
+                    owner,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
 
-        self.duped_transaction(&d.tx)?;
-        self.reject_negative_amount(&d.amount)?;
+    fn handle_withdrawal(&mut self, w: &Withdrawal) -> Result<(), TransactionManagerError> {
+        trace!(?w);
+
+        let Some(currency_index) = self.history.currency_index(w.currency) else {
+            return Err(TransactionManagerError::InvalidTransaction {
+                client: w.client,
+                tx: w.tx,
+                reason: "Too many currencies to record it".to_string(),
+            });
+        };
 
         let mut registry = self.balances.write().unwrap();
 
-        let client_account = registry.client_balances.entry(d.client).or_default();
-        trace!("client_account, prior: {client_account:?}");
+        let client_account = registry.client_balances.entry(w.client).or_default();
+        trace!(?client_account, "prior");
+
+        let overdraft_limit = client_account.overdraft_limit_in(w.currency);
+        let funds = client_account.funds.entry(w.currency).or_default();
 
-        if client_account.locked {
-            return Err(TransactionManagerError::AccountLocked);
+        let fee = self.fee_schedule.withdrawal_fee(w.amount);
+        let available = funds.available + overdraft_limit;
+
+        if available - w.amount - fee < 0.0 {
+            return Err(TransactionManagerError::InsufficientFunds {
+                client: w.client,
+                tx: w.tx,
+                requested: w.amount + fee,
+                available,
+            });
         }
 
-        client_account.total += d.amount;
-        client_account.available += d.amount;
+        funds.total -= w.amount + fee;
+        funds.available -= w.amount + fee;
 
-        trace!("client_account, after: {client_account:?}");
+        trace!(?client_account, "after");
 
-        self.history.insert(d.tx, Transaction::Deposit(d.clone()));
+        self.history.insert_withdrawal(
+            w.tx,
+            WithdrawalRecord::new(w.client, w.amount).with_currency(currency_index),
+        );
+        if fee > 0.0 {
+            self.history.insert_levied_fee(w.tx, fee);
+        }
 
-        trace!("history: {:?}", self.history);
+        trace!(history_entries = self.history.len());
 
         Ok(())
     }
 
-    // TODO: It seems to me that disputes really only apply to deposits, right?
-    // When I read the text it seems to indicate that, e.g. "This means
-    // that the clients available funds should decrease by the amount disputed"
-    // => Performing this operation on a transaction that's a withdrawal would then
-    // seem to mean that we're decreasing the available funds, or would we in that
-    // case be increasing the available funds? Hmm. Let's start by considering
-    // only deposits and revisit
-    fn handle_dispute(&mut self, d: &Dispute) -> Result<(), TransactionManagerError> {
-        debug!("{d:?}");
+    fn handle_deposit(&mut self, d: &Deposit) -> Result<(), TransactionManagerError> {
+        trace!(?d);
+
+        let Some(currency_index) = self.history.currency_index(d.currency) else {
+            return Err(TransactionManagerError::InvalidTransaction {
+                client: d.client,
+                tx: d.tx,
+                reason: "Too many currencies to record it".to_string(),
+            });
+        };
 
         let mut registry = self.balances.write().unwrap();
 
         let client_account = registry.client_balances.entry(d.client).or_default();
-        trace!("client_account, prior: {client_account:?}");
+        trace!(?client_account, "prior");
 
-        if client_account.locked {
-            return Err(TransactionManagerError::AccountLocked);
-        }
+        let funds = client_account.funds.entry(d.currency).or_default();
 
-        let disputed_transaction = self.history.get(&d.tx);
-        trace!("disputed_transaction: {disputed_transaction:?}");
+        let fee = self.fee_schedule.deposit_fee(d.amount);
 
-        // Assuming that disputes, resolves, and chargebacks only apply to deposits,
-        // which seems to make sense
-        let Some(Transaction::Deposit(dep)) = disputed_transaction else {
-            return Err(TransactionManagerError::DisputedTransactionDoesNotExist(
-                d.tx,
-            ));
-        };
+        funds.total += d.amount - fee;
+        funds.available += d.amount - fee;
 
-        // TODO: Is it possible for this to go negative? Should check
-        client_account.available -= dep.amount;
-        client_account.held += dep.amount;
+        trace!(?client_account, "after");
 
-        client_account.disputed_transactions.insert(d.tx);
+        self.history.insert_deposit(
+            d.tx,
+            DepositRecord::new(d.client, d.amount).with_currency(currency_index),
+        );
+        if fee > 0.0 {
+            self.history.insert_levied_fee(d.tx, fee);
+        }
+        if let Some(timestamp) = d.timestamp {
+            self.history.insert_deposit_timestamp(d.tx, timestamp);
+        }
 
-        trace!("client_account, after: {client_account:?}");
+        trace!(history_entries = self.history.len());
 
         Ok(())
     }
 
-    // TODO: It would appear that there's not a description of what operations should
-    // be allowed if an account is locked / frozen (as far as I can tell).
-    // => ASSUMPTION: Locked accounts can have not operations performed on them,
-    //                perhaps they need some sort of manual intervention
-    fn handle_chargeback(&mut self, c: &Chargeback) -> Result<(), TransactionManagerError> {
-        debug!("{c:?}");
-
-        // TODO: Need to allow like... a single dispute at once?
+    fn handle_fee(&mut self, f: &Fee) -> Result<(), TransactionManagerError> {
+        trace!(?f);
 
         let mut registry = self.balances.write().unwrap();
 
-        let client_account = registry.client_balances.entry(c.client).or_default();
-        trace!("client_account, prior: {client_account:?}");
+        let client_account = registry.client_balances.entry(f.client).or_default();
+        trace!(?client_account, "prior");
 
-        if client_account.locked {
-            return Err(TransactionManagerError::AccountLocked);
-        }
+        let overdraft_limit = client_account.overdraft_limit_in(f.currency);
+        let funds = client_account.funds.entry(f.currency).or_default();
 
-        let was_disputed = client_account.disputed_transactions.remove(&c.tx);
+        let available = funds.available + overdraft_limit;
 
-        if !was_disputed {
-            return Err(TransactionManagerError::NoOpenDispute(c.tx));
+        if available - f.amount < 0.0 {
+            return Err(TransactionManagerError::InsufficientFunds {
+                client: f.client,
+                tx: f.tx,
+                requested: f.amount,
+                available,
+            });
         }
 
-        let disputed_transaction = self.history.get(&c.tx);
-        trace!("disputed_transaction: {disputed_transaction:?}");
+        funds.total -= f.amount;
+        funds.available -= f.amount;
 
-        // Assuming that disputes, resolves, and chargebacks only apply to deposits,
-        // which seems to make sense
-        let Some(Transaction::Deposit(dep)) = disputed_transaction else {
-            return Err(TransactionManagerError::DisputedTransactionDoesNotExist(
-                c.tx,
-            ));
-        };
+        trace!(?client_account, "after");
+
+        self.history.insert_non_disputable(f.tx);
+
+        trace!(history_entries = self.history.len());
+
+        Ok(())
+    }
+
+    fn handle_interest(&mut self, i: &Interest) -> Result<(), TransactionManagerError> {
+        trace!(?i);
+
+        let mut registry = self.balances.write().unwrap();
+
+        let client_account = registry.client_balances.entry(i.client).or_default();
+        trace!(?client_account, "prior");
 
-        // TODO: Is it possible for this to go negative? Should check
-        client_account.total -= dep.amount;
-        client_account.held -= dep.amount;
+        let funds = client_account.funds.entry(i.currency).or_default();
 
-        let _ = client_account.disputed_transactions.remove(&c.tx);
+        funds.total += i.amount;
+        funds.available += i.amount;
 
-        client_account.locked = true;
+        trace!(?client_account, "after");
 
-        trace!("client_account, after: {client_account:?}");
+        self.history.insert_non_disputable(i.tx);
+
+        trace!(history_entries = self.history.len());
 
         Ok(())
     }
 
-    fn handle_resolve(&mut self, r: &Resolve) -> Result<(), TransactionManagerError> {
-        debug!("{r:?}");
+    // Being administrative, overdraft limits can be set even on locked accounts
+    fn handle_overdraft_limit(
+        &mut self,
+        o: &OverdraftLimit,
+    ) -> Result<(), TransactionManagerError> {
+        trace!(?o);
 
-        let mut registry = self.balances.write().unwrap();
+        self.apply_overdraft_limit(o.client, o.limit);
 
-        let client_account = registry.client_balances.entry(r.client).or_default();
-        trace!("client_account, prior: {client_account:?}");
+        self.history.insert_non_disputable(o.tx);
 
-        if client_account.locked {
-            return Err(TransactionManagerError::AccountLocked);
-        }
+        trace!(history_entries = self.history.len());
 
-        let was_disputed = client_account.disputed_transactions.remove(&r.tx);
+        Ok(())
+    }
 
-        if !was_disputed {
-            return Err(TransactionManagerError::NoOpenDispute(r.tx));
+    // A held transaction which can no longer be applied, e.g. as the client no longer has
+    // the funds for it, stays held
+    fn handle_approve(&mut self, a: &Approve) -> Result<(), TransactionManagerError> {
+        trace!(?a);
+
+        self.pending_transaction(a.client, a.tx)?;
+        // Taken out first, as its id is reserved for as long as it's held
+        let (position, pending) = self.history.take_pending(a.tx).unwrap();
+
+        // Things may have changed since it was held, e.g. the account may have been locked.
+        // A rejected transaction hasn't changed anything, so it's simply held again. Nothing
+        // which can fail after a change is made, like writing to the idempotency index,
+        // may happen in here
+        if let Err(e) = self.validate(&pending).and_then(|()| self.apply(&pending)) {
+            self.history.restore_pending(position, pending);
+            return Err(e);
         }
 
-        let disputed_transaction = self.history.get(&r.tx);
-        trace!("disputed_transaction: {disputed_transaction:?}");
+        trace!(pending_entries = self.history.pending().len());
 
-        // Assuming that disputes, resolves, and chargebacks only apply to deposits,
-        // which seems to make sense
-        let Some(Transaction::Deposit(dep)) = disputed_transaction else {
-            return Err(TransactionManagerError::DisputedTransactionDoesNotExist(
-                r.tx,
-            ));
-        };
+        Ok(())
+    }
+
+    fn handle_decline(&mut self, d: &Decline) -> Result<(), TransactionManagerError> {
+        trace!(?d);
 
-        // TODO: Is it possible for this to go negative? Should check
-        client_account.available += dep.amount;
-        client_account.held -= dep.amount;
+        self.pending_transaction(d.client, d.tx)?;
+        self.history.take_pending(d.tx);
 
-        trace!("client_account, after: {client_account:?}");
+        trace!(pending_entries = self.history.pending().len());
 
         Ok(())
     }
-}
 
-impl Default for TransactionManager {
-    fn default() -> Self {
-        Self::new()
+    fn pending_transaction(
+        &self,
+        client: u16,
+        tx: u32,
+    ) -> Result<Transaction, TransactionManagerError> {
+        self.history
+            .pending_transaction(tx)
+            .cloned()
+            .ok_or(TransactionManagerError::NoPendingTransaction { client, tx })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::balance::{ClientBalance, ClientBalanceRegistry};
-    use crate::transactions::{Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal};
-    use std::collections::{HashMap, HashSet};
-    use std::sync::Once;
+    fn handle_exchange(&mut self, e: &Exchange) -> Result<(), TransactionManagerError> {
+        trace!(?e);
+
+        let Some(rate) = self
+            .rate_provider
+            .as_ref()
+            .and_then(|provider| provider.rate(e.from_currency, e.to_currency))
+        else {
+            return Err(TransactionManagerError::NoExchangeRate {
+                client: e.client,
+                tx: e.tx,
+                from: e.from_currency,
+                to: e.to_currency,
+            });
+        };
+        trace!(rate);
 
-    // TODO: Consider a few corner cases here
-    // * rejecting transaction IDs we've already seen?
-    // * rejecting if amount is negative?
-    // * handling a withdrawal to an account we're seeing for the first time
+        let mut registry = self.balances.write().unwrap();
+
+        let client_account = registry.client_balances.entry(e.client).or_default();
+        trace!(?client_account, "prior");
+
+        let overdraft_limit = client_account.overdraft_limit_in(Some(e.from_currency));
+        let from_funds = client_account
+            .funds
+            .entry(Some(e.from_currency))
+            .or_default();
+
+        let available = from_funds.available + overdraft_limit;
+
+        if available - e.amount < 0.0 {
+            return Err(TransactionManagerError::InsufficientFunds {
+                client: e.client,
+                tx: e.tx,
+                requested: e.amount,
+                available,
+            });
+        }
+
+        from_funds.total -= e.amount;
+        from_funds.available -= e.amount;
+
+        let to_funds = client_account.funds.entry(Some(e.to_currency)).or_default();
+
+        to_funds.total += e.amount * rate;
+        to_funds.available += e.amount * rate;
+
+        trace!(?client_account, "after");
+
+        self.history.insert_exchange(e.tx, rate);
+
+        trace!(history_entries = self.history.len());
+
+        Ok(())
+    }
+
+    // Transfers are applied to both accounts or neither, so every check is done before
+    // touching either of them
+    fn handle_transfer(&mut self, t: &Transfer) -> Result<(), TransactionManagerError> {
+        trace!(?t);
+
+        if t.from_client == t.to_client {
+            return Err(TransactionManagerError::InvalidTransaction {
+                client: t.from_client,
+                tx: t.tx,
+                reason: "Transfer is from and to the same client".to_string(),
+            });
+        }
+
+        let Some(currency_index) = self.history.currency_index(t.currency) else {
+            return Err(TransactionManagerError::InvalidTransaction {
+                client: t.from_client,
+                tx: t.tx,
+                reason: "Too many currencies to record it".to_string(),
+            });
+        };
+
+        let mut registry = self.balances.write().unwrap();
+
+        let sender_account = registry.client_balances.entry(t.from_client).or_default();
+        trace!(?sender_account, "prior");
+
+        let overdraft_limit = sender_account.overdraft_limit_in(t.currency);
+        let sender_funds = sender_account.funds.entry(t.currency).or_default();
+
+        let available = sender_funds.available + overdraft_limit;
+
+        if available - t.amount < 0.0 {
+            return Err(TransactionManagerError::InsufficientFunds {
+                client: t.from_client,
+                tx: t.tx,
+                requested: t.amount,
+                available,
+            });
+        }
+
+        sender_funds.total -= t.amount;
+        sender_funds.available -= t.amount;
+
+        trace!(?sender_account, "after");
+
+        let recipient_account = registry.client_balances.entry(t.to_client).or_default();
+        trace!(?recipient_account, "prior");
+
+        let recipient_funds = recipient_account.funds.entry(t.currency).or_default();
+
+        recipient_funds.total += t.amount;
+        recipient_funds.available += t.amount;
+
+        trace!(?recipient_account, "after");
+
+        self.history.insert_transfer(
+            t.tx,
+            t.from_client,
+            DepositRecord::new(t.to_client, t.amount).with_currency(currency_index),
+        );
+        if let Some(timestamp) = t.timestamp {
+            self.history.insert_deposit_timestamp(t.tx, timestamp);
+        }
+
+        trace!(history_entries = self.history.len());
+
+        Ok(())
+    }
+
+    // TODO: It seems to me that disputes really only apply to deposits, right?
+    // When I read the text it seems to indicate that, e.g. "This means
+    // that the clients available funds should decrease by the amount disputed"
+    // => Performing this operation on a transaction that's a withdrawal would then
+    // seem to mean that we're decreasing the available funds, or would we in that
+    // case be increasing the available funds? Hmm. Let's start by considering
+    // only deposits and revisit
+    fn handle_dispute(&mut self, d: &Dispute) -> Result<(), TransactionManagerError> {
+        trace!(?d);
+
+        let mut registry = self.balances.write().unwrap();
+
+        let client_account = registry.client_balances.entry(d.client).or_default();
+        trace!(?client_account, "prior");
+
+        // Disputes without a timestamp of their own are considered raised now
+        let disputed_at = d.timestamp.or(self.now);
+
+        if let (Some(deposited_at), Some(disputed_at)) =
+            (self.history.deposit_timestamp(d.tx), disputed_at)
+        {
+            if self.dispute_policy.is_late(deposited_at, disputed_at) {
+                return Err(TransactionManagerError::DisputeWindowExpired {
+                    client: d.client,
+                    tx: d.tx,
+                    deposited_at,
+                    disputed_at,
+                });
+            }
+        }
+
+        // Disputes are always in the currency of the deposit
+        let currency = self
+            .history
+            .deposit(d.tx)
+            .and_then(|dep| self.history.currency(dep.currency));
+
+        let disputed_transaction = self.history.deposit(d.tx).copied();
+        trace!(?disputed_transaction);
+
+        // Assuming that disputes, resolves, and chargebacks only apply to deposits,
+        // which seems to make sense
+        let Some(dep) = disputed_transaction else {
+            return Err(TransactionManagerError::DisputedTransactionDoesNotExist {
+                client: d.client,
+                tx: d.tx,
+            });
+        };
+
+        if dep.state == TransactionState::Reversed {
+            return Err(TransactionManagerError::AlreadyReversed {
+                client: d.client,
+                tx: d.tx,
+            });
+        }
+
+        // Only what was credited can be held, not any fee levied on the deposit
+        let credited = dep.amount - self.history.levied_fee(d.tx).unwrap_or(0.0);
+        let undisputed = self.history.disputed_amounts(d.tx).undisputed(credited);
+
+        if undisputed <= AMOUNT_TOLERANCE {
+            return Err(TransactionManagerError::TransactionAlreadyDisputed {
+                client: d.client,
+                tx: d.tx,
+            });
+        }
+
+        let amount = d.amount.unwrap_or(undisputed);
+
+        // Negative amounts have already been rejected, but nothing can be held of nothing
+        if amount <= AMOUNT_TOLERANCE {
+            return Err(TransactionManagerError::InvalidTransaction {
+                client: d.client,
+                tx: d.tx,
+                reason: "Disputed amount must be positive".to_string(),
+            });
+        }
+
+        if amount > undisputed + AMOUNT_TOLERANCE {
+            return Err(TransactionManagerError::DisputedAmountExceedsUndisputed {
+                client: d.client,
+                tx: d.tx,
+                requested: amount,
+                undisputed,
+            });
+        }
+
+        let overdraft_limit = client_account.overdraft_limit_in(currency);
+        let funds = client_account.funds.entry(currency).or_default();
+
+        // The deposit may already have been withdrawn, in which case holding it would take
+        // the available funds negative
+        let spare = funds.available + overdraft_limit;
+        let amount = if amount <= spare + AMOUNT_TOLERANCE {
+            amount
+        } else {
+            self.negative_balance_policy.hold_for(amount, spare).ok_or(
+                TransactionManagerError::DisputeWouldOverdraw {
+                    client: d.client,
+                    tx: d.tx,
+                    requested: amount,
+                    available: spare,
+                },
+            )?
+        };
+
+        // Only once capped by the policy
+        if amount <= AMOUNT_TOLERANCE {
+            return Err(TransactionManagerError::NoFundsAvailableToHold {
+                client: d.client,
+                tx: d.tx,
+            });
+        }
+
+        funds.available -= amount;
+        funds.held += amount;
+
+        // Only now that the dispute is applied are its amounts kept track of
+        self.history.hold_disputed(d.tx, amount);
+        if client_account.disputed_transactions.insert(d.tx) {
+            self.open_disputes += 1;
+        }
+
+        if let Some(disputed_at) = disputed_at {
+            self.history.open_dispute(d.tx, disputed_at);
+        }
+
+        trace!(?client_account, "after");
+
+        Ok(())
+    }
+
+    fn handle_chargeback(&mut self, c: &Chargeback) -> Result<(), TransactionManagerError> {
+        trace!(?c);
+
+        let mut registry = self.balances.write().unwrap();
+
+        let client_account = registry.client_balances.entry(c.client).or_default();
+        trace!(?client_account, "prior");
+
+        if !client_account.disputed_transactions.contains(&c.tx) {
+            return Err(TransactionManagerError::NoOpenDispute {
+                client: c.client,
+                tx: c.tx,
+            });
+        }
+
+        // Disputes are always in the currency of the deposit
+        let currency = self
+            .history
+            .deposit(c.tx)
+            .and_then(|dep| self.history.currency(dep.currency));
+        let from_client = self.history.transfer_sender(c.tx);
+
+        let disputed_transaction = self.history.disputed_deposit_mut(c.tx);
+        trace!(?disputed_transaction);
+
+        // Assuming that disputes, resolves, and chargebacks only apply to deposits,
+        // which seems to make sense
+        let Some((dep, disputed)) = disputed_transaction else {
+            return Err(TransactionManagerError::DisputedTransactionDoesNotExist {
+                client: c.client,
+                tx: c.tx,
+            });
+        };
+
+        let amount = c.amount.unwrap_or(disputed.held);
+
+        if amount > disputed.held + AMOUNT_TOLERANCE {
+            return Err(TransactionManagerError::AmountExceedsHeld {
+                client: c.client,
+                tx: c.tx,
+                requested: amount,
+                held: disputed.held,
+            });
+        }
+
+        let funds = client_account.funds.entry(currency).or_default();
+
+        // Can't be more than was held by the dispute, so held never goes negative. Total
+        // only goes negative if the dispute already took the available funds negative
+        funds.total -= amount;
+        funds.held -= amount;
+
+        disputed.held -= amount;
+        disputed.charged_back += amount;
+
+        // Whatever remains held stays disputed, although with the account now locked it
+        // needs manual intervention to go anywhere
+        if disputed.held <= AMOUNT_TOLERANCE {
+            disputed.held = 0.0;
+            dep.state = TransactionState::ChargedBack;
+            if client_account.disputed_transactions.remove(&c.tx) {
+                self.open_disputes -= 1;
+            }
+            self.history.close_dispute(c.tx);
+        }
+
+        if !client_account.locked {
+            client_account.locked = true;
+            self.locked_accounts += 1;
+        }
+
+        trace!(?client_account, "after");
+
+        // A charged back transfer goes back to the client who sent it, even if their own
+        // account has been locked since
+        if let Some(from_client) = from_client {
+            let sender_account = registry.client_balances.entry(from_client).or_default();
+            let sender_funds = sender_account.funds.entry(currency).or_default();
+
+            sender_funds.total += amount;
+            sender_funds.available += amount;
+
+            trace!(?sender_account, "after");
+        }
+
+        Ok(())
+    }
+
+    // Being administrative, reversals can be made even on locked accounts. Reversing a
+    // deposit can't take the available funds beyond the overdraft limit though, any more
+    // than a withdrawal could
+    fn handle_reversal(&mut self, r: &Reversal) -> Result<(), TransactionManagerError> {
+        trace!(?r);
+
+        if let Some(dep) = self.history.deposit(r.tx).copied() {
+            self.reverse_deposit(r, dep)?;
+        } else if let Some(w) = self.history.withdrawal(r.tx).copied() {
+            self.reverse_withdrawal(r, w)?;
+        } else {
+            return Err(TransactionManagerError::TransactionNotReversible {
+                client: r.client,
+                tx: r.tx,
+            });
+        }
+
+        trace!(history_entries = self.history.len());
+
+        Ok(())
+    }
+
+    fn reverse_deposit(
+        &mut self,
+        r: &Reversal,
+        dep: DepositRecord,
+    ) -> Result<(), TransactionManagerError> {
+        // Transfers involve two clients, so they're left to disputes
+        if self.history.transfer_sender(r.tx).is_some() {
+            return Err(TransactionManagerError::TransactionNotReversible {
+                client: r.client,
+                tx: r.tx,
+            });
+        }
+
+        match dep.state {
+            TransactionState::Settled => {}
+            TransactionState::Reversed => {
+                return Err(TransactionManagerError::AlreadyReversed {
+                    client: r.client,
+                    tx: r.tx,
+                })
+            }
+            TransactionState::Disputed | TransactionState::ChargedBack => {
+                return Err(TransactionManagerError::ReversalOfDisputedTransaction {
+                    client: r.client,
+                    tx: r.tx,
+                })
+            }
+        }
+
+        let currency = self.history.currency(dep.currency);
+        // Only what was credited is taken back, not any fee levied on the deposit
+        let amount = dep.amount - self.history.levied_fee(r.tx).unwrap_or(0.0);
+
+        let mut registry = self.balances.write().unwrap();
+
+        let client_account = registry.client_balances.entry(r.client).or_default();
+        trace!(?client_account, "prior");
+
+        let overdraft_limit = client_account.overdraft_limit_in(currency);
+        let funds = client_account.funds.entry(currency).or_default();
+
+        let available = funds.available + overdraft_limit;
+
+        if available - amount < 0.0 {
+            return Err(TransactionManagerError::InsufficientFunds {
+                client: r.client,
+                tx: r.tx,
+                requested: amount,
+                available,
+            });
+        }
+
+        funds.total -= amount;
+        funds.available -= amount;
+
+        trace!(?client_account, "after");
+
+        if let Some(dep) = self.history.deposit_mut(r.tx) {
+            dep.state = TransactionState::Reversed;
+        }
+
+        Ok(())
+    }
+
+    fn reverse_withdrawal(
+        &mut self,
+        r: &Reversal,
+        w: WithdrawalRecord,
+    ) -> Result<(), TransactionManagerError> {
+        if w.state == TransactionState::Reversed {
+            return Err(TransactionManagerError::AlreadyReversed {
+                client: r.client,
+                tx: r.tx,
+            });
+        }
+
+        let currency = self.history.currency(w.currency);
+        // Any fee levied on the withdrawal is refunded along with it
+        let amount = w.amount + self.history.levied_fee(r.tx).unwrap_or(0.0);
+
+        let mut registry = self.balances.write().unwrap();
+
+        let client_account = registry.client_balances.entry(r.client).or_default();
+        trace!(?client_account, "prior");
+
+        let funds = client_account.funds.entry(currency).or_default();
+
+        funds.total += amount;
+        funds.available += amount;
+
+        trace!(?client_account, "after");
+
+        if let Some(w) = self.history.withdrawal_mut(r.tx) {
+            w.state = TransactionState::Reversed;
+        }
+
+        Ok(())
+    }
+
+    fn handle_resolve(&mut self, r: &Resolve) -> Result<(), TransactionManagerError> {
+        trace!(?r);
+
+        let mut registry = self.balances.write().unwrap();
+
+        let client_account = registry.client_balances.entry(r.client).or_default();
+        trace!(?client_account, "prior");
+
+        if !client_account.disputed_transactions.contains(&r.tx) {
+            return Err(TransactionManagerError::NoOpenDispute {
+                client: r.client,
+                tx: r.tx,
+            });
+        }
+
+        // Disputes are always in the currency of the deposit
+        let currency = self
+            .history
+            .deposit(r.tx)
+            .and_then(|dep| self.history.currency(dep.currency));
+
+        let disputed_transaction = self.history.disputed_deposit_mut(r.tx);
+        trace!(?disputed_transaction);
+
+        // Assuming that disputes, resolves, and chargebacks only apply to deposits,
+        // which seems to make sense
+        let Some((dep, disputed)) = disputed_transaction else {
+            return Err(TransactionManagerError::DisputedTransactionDoesNotExist {
+                client: r.client,
+                tx: r.tx,
+            });
+        };
+
+        let amount = r.amount.unwrap_or(disputed.held);
+
+        if amount > disputed.held + AMOUNT_TOLERANCE {
+            return Err(TransactionManagerError::AmountExceedsHeld {
+                client: r.client,
+                tx: r.tx,
+                requested: amount,
+                held: disputed.held,
+            });
+        }
+
+        let funds = client_account.funds.entry(currency).or_default();
+
+        // Can't be more than was held by the dispute, so held never goes negative
+        funds.available += amount;
+        funds.held -= amount;
+
+        disputed.held -= amount;
+
+        if disputed.held <= AMOUNT_TOLERANCE {
+            disputed.held = 0.0;
+            dep.state = if disputed.charged_back > 0.0 {
+                TransactionState::ChargedBack
+            } else {
+                TransactionState::Settled
+            };
+            if client_account.disputed_transactions.remove(&r.tx) {
+                self.open_disputes -= 1;
+            }
+            self.history.close_dispute(r.tx);
+        }
+
+        trace!(?client_account, "after");
+
+        Ok(())
+    }
+}
+
+// The manager has to stay `Send`, so that it can be handed to another thread along with
+// its balances
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<TransactionManager>();
+};
+
+impl Default for TransactionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::{ClientBalance, ClientBalanceRegistry, Funds};
+    use crate::currency::{Currency, StaticRates};
+    use crate::fees::FeeRate;
+    use crate::rules::{Condition, Rule};
+    use crate::simulation::Rejection;
+    use crate::transactions::{
+        Chargeback, Deposit, Dispute, Exchange, Fee, Interest, Resolve, Reversal, Transaction,
+        Transfer, Withdrawal,
+    };
+    use crate::validators::BlockedClients;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Once;
+
+    // TODO: Consider a few corner cases here
+    // * rejecting transaction IDs we've already seen?
+    // * rejecting if amount is negative?
+    // * handling a withdrawal to an account we're seeing for the first time
     //   => Should this still create the account, but not place anything or not create the account?
     //   => For now, I'll go with creating the account
 
     static INIT: Once = Once::new();
 
-    fn test_setup() {
-        INIT.call_once(|| env_logger::init());
-    }
+    fn test_setup() {
+        INIT.call_once(|| {
+            tracing_subscriber::fmt()
+                .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+                .with_test_writer()
+                .init()
+        });
+    }
+
+    #[test]
+    fn test_rejecting_duplicate_transaction_ids() {}
+
+    #[test]
+    fn test_negative_amount_deposit() {}
+
+    #[test]
+    fn test_simple_deposit_withdrawal() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let deposit = Transaction::Deposit(Deposit::new(1, 1, 32.0));
+        let withdrawal = Transaction::Withdrawal(Withdrawal::new(1, 2, 20.0));
+
+        tm.record_transaction(&deposit).unwrap();
+        tm.record_transaction(&withdrawal).unwrap();
+
+        let mut internal = HashMap::new();
+        let client_1_balance = ClientBalance::new(12.0, 0.0, 12.0, false, HashSet::new());
+        internal.insert(1, client_1_balance);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    // TODO: Using test-case would probably simplify this by letting us have various
+    // setups which differ only in the expected input and output
+    #[test]
+    fn test_multiple_clients_deposit_withdrawal() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(2, 3, 2.0)),
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Withdrawal(Withdrawal::new(2, 4, 1.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 20.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+        let client_1_balance = ClientBalance::new(12.0, 0.0, 12.0, false, HashSet::new());
+        let client_2_balance = ClientBalance::new(1.0, 0.0, 1.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_insufficient_funds_for_withdrawal_first_operation() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transaction = Transaction::Withdrawal(Withdrawal::new(2, 4, 200.0));
+
+        let err = tm.record_transaction(&transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 2,
+                tx: 4,
+                requested: 200.0,
+                available: 0.0
+            }
+        );
+        assert_eq!(err.code(), "insufficient_funds");
+        assert_eq!(
+            err.to_string(),
+            "[insufficient_funds] Insufficient funds for tx 4 of client 2: requested 200, available 0"
+        );
+
+        let client_2_balance = ClientBalance::new(0.0, 0.0, 0.0, false, HashSet::new());
+        let internal = HashMap::from([(2, client_2_balance)]);
+
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_simple_dispute() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let client_1_balance = ClientBalance::new(0.0, 32.0, 32.0, false, HashSet::from([1]));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_simple_dispute_resolve() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_simple_dispute_chargeback() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let client_1_balance = ClientBalance::new(0.0, 0.0, 0.0, true, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_chargeback_blocks_transactions() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Deposit(Deposit::new(1, 2, 42.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+
+        assert_eq!(
+            err,
+            TransactionManagerError::AccountLocked { client: 1, tx: 2 }
+        );
+
+        let client_1_balance = ClientBalance::new(0.0, 0.0, 0.0, true, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_block_duplicated_transactions() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![Transaction::Deposit(Deposit::new(1, 1, 32.0))];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Deposit(Deposit::new(1, 1, 42.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+
+        assert_eq!(
+            err,
+            TransactionManagerError::DuplicateTransactionId { client: 1, tx: 1 }
+        );
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_block_negative_amount() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![Transaction::Deposit(Deposit::new(1, 1, 32.0))];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Deposit(Deposit::new(1, 2, -42.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+
+        assert_eq!(
+            err,
+            TransactionManagerError::NegativeAmountNotAllowed {
+                client: 1,
+                tx: 2,
+                amount: -42.0
+            }
+        );
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_resolve_dispute_that_doesnt_exist() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Resolve(Resolve::new(1, 1));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::NoOpenDispute { client: 1, tx: 1 }
+        );
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_chargeback_dispute_that_doesnt_exist() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Chargeback(Chargeback::new(1, 1));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::NoOpenDispute { client: 1, tx: 1 }
+        );
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_idempotency_index_skips_replayed_transactions() {
+        test_setup();
+
+        let mut tm = TransactionManager::new().with_idempotency_index(IdempotencyIndex::new());
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        for transaction in &transactions {
+            let err = tm.record_transaction(transaction).unwrap_err();
+            assert_eq!(
+                err,
+                TransactionManagerError::AlreadyApplied { client: 1, tx: 1 }
+            );
+        }
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_idempotency_index_skips_replayed_rejections() {
+        test_setup();
+
+        let mut tm = TransactionManager::new().with_idempotency_index(IdempotencyIndex::new());
+
+        let first_file = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 10.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 20.0)),
+        ];
+
+        tm.record_transaction(&first_file[0]).unwrap();
+        let err = tm.record_transaction(&first_file[1]).unwrap_err();
+        assert_eq!(err.code(), "insufficient_funds");
+
+        tm.record_transaction(&Transaction::Deposit(Deposit::new(1, 3, 50.0)))
+            .unwrap();
+
+        for transaction in &first_file {
+            let err = tm.record_transaction(transaction).unwrap_err();
+            assert_eq!(
+                err,
+                TransactionManagerError::AlreadyApplied {
+                    client: 1,
+                    tx: transaction.tx()
+                }
+            );
+        }
+
+        let client_1_balance = ClientBalance::new(60.0, 0.0, 60.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        assert_eq!(tm.retrieve_client_balances(), expected_balances);
+    }
+
+    #[test]
+    fn test_idempotency_index_allows_disputing_again_after_resolving() {
+        test_setup();
+
+        let mut tm = TransactionManager::new().with_idempotency_index(IdempotencyIndex::new());
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0).with_timestamp(1)),
+            Transaction::Dispute(Dispute::new(1, 1).with_timestamp(2)),
+            Transaction::Resolve(Resolve::new(1, 1).with_timestamp(3)),
+            Transaction::Dispute(Dispute::new(1, 1).with_timestamp(4)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let client_1_balance = ClientBalance::new(0.0, 32.0, 32.0, false, HashSet::from([1]));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        assert_eq!(tm.retrieve_client_balances(), expected_balances);
+    }
+
+    #[test]
+    fn test_dispute_already_disputed_transaction() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 1));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::TransactionAlreadyDisputed { client: 1, tx: 1 }
+        );
+
+        let client_1_balance = ClientBalance::new(0.0, 32.0, 32.0, false, HashSet::from([1]));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_dispute_outside_window_rejected() {
+        test_setup();
+
+        let mut tm =
+            TransactionManager::new().with_dispute_policy(DisputePolicy::new(Some(100), None));
+
+        let transactions = vec![Transaction::Deposit(
+            Deposit::new(1, 1, 32.0).with_timestamp(1_000),
+        )];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 1).with_timestamp(1_101));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::DisputeWindowExpired {
+                client: 1,
+                tx: 1,
+                deposited_at: 1_000,
+                disputed_at: 1_101
+            }
+        );
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_expired_dispute_resolved_automatically() {
+        test_setup();
+
+        let mut tm =
+            TransactionManager::new().with_dispute_policy(DisputePolicy::new(None, Some(50)));
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0).with_timestamp(1_000)),
+            Transaction::Dispute(Dispute::new(1, 1).with_timestamp(1_010)),
+            Transaction::Deposit(Deposit::new(2, 2, 5.0).with_timestamp(1_059)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        // Still within the allowed duration
+        let client_1_balance = ClientBalance::new(0.0, 32.0, 32.0, false, HashSet::from([1]));
+        let client_2_balance = ClientBalance::new(5.0, 0.0, 5.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance.clone())]);
+        assert_eq!(
+            tm.retrieve_client_balances(),
+            ClientBalanceRegistry::load_registry(internal)
+        );
+
+        tm.advance_time(1_060);
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+
+        // Nothing left to resolve
+        let err = tm
+            .record_transaction(&Transaction::Resolve(Resolve::new(1, 1)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::NoOpenDispute { client: 1, tx: 1 }
+        );
+    }
+
+    #[test]
+    fn test_rejected_transaction_does_not_move_clock() {
+        test_setup();
+
+        let mut tm =
+            TransactionManager::new().with_dispute_policy(DisputePolicy::new(None, Some(50)));
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0).with_timestamp(1_000)),
+            Transaction::Dispute(Dispute::new(1, 1).with_timestamp(1_010)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction =
+            Transaction::Deposit(Deposit::new(2, 1, 5.0).with_timestamp(5_000));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::DuplicateTransactionId { client: 2, tx: 1 }
+        );
+
+        // The dispute is still open, and can be resolved as usual
+        let client_1_balance = ClientBalance::new(0.0, 32.0, 32.0, false, HashSet::from([1]));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        assert_eq!(
+            tm.retrieve_client_balances(),
+            ClientBalanceRegistry::load_registry(internal)
+        );
+
+        tm.record_transaction(&Transaction::Resolve(
+            Resolve::new(1, 1).with_timestamp(1_020),
+        ))
+        .unwrap();
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_partial_disputes_bounded_by_deposit() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Dispute(Dispute::new(1, 1).with_amount(30.0)),
+            Transaction::Dispute(Dispute::new(1, 1).with_amount(50.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 1).with_amount(25.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::DisputedAmountExceedsUndisputed {
+                client: 1,
+                tx: 1,
+                requested: 25.0,
+                undisputed: 20.0
+            }
+        );
+
+        // Disputes whatever is left
+        tm.record_transaction(&Transaction::Dispute(Dispute::new(1, 1)))
+            .unwrap();
+
+        let client_1_balance = ClientBalance::new(0.0, 100.0, 100.0, false, HashSet::from([1]));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_partial_disputes_with_idempotency_index() {
+        test_setup();
+
+        let mut tm = TransactionManager::new().with_idempotency_index(IdempotencyIndex::new());
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Dispute(Dispute::new(1, 1).with_amount(30.0)),
+            Transaction::Dispute(Dispute::new(1, 1).with_amount(20.0)),
+            Transaction::Resolve(Resolve::new(1, 1).with_amount(10.0)),
+            Transaction::Resolve(Resolve::new(1, 1).with_amount(15.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        // Only a re-sent row is skipped
+        let err = tm.record_transaction(&transactions[2]).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::AlreadyApplied { client: 1, tx: 1 }
+        );
+
+        let client_1_balance = ClientBalance::new(75.0, 25.0, 100.0, false, HashSet::from([1]));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        assert_eq!(tm.retrieve_client_balances(), expected_balances);
+    }
+
+    #[test]
+    fn test_rejected_disputes_keep_no_disputed_amounts() {
+        test_setup();
+
+        let mut tm =
+            TransactionManager::new().with_dispute_policy(DisputePolicy::new(Some(100), None));
+
+        tm.record_transaction(&Transaction::Deposit(
+            Deposit::new(1, 1, 100.0).with_timestamp(1_000),
+        ))
+        .unwrap();
+
+        let blocked_transactions = vec![
+            Transaction::Dispute(Dispute::new(1, 1).with_amount(150.0)),
+            Transaction::Dispute(Dispute::new(2, 1)),
+            Transaction::Dispute(Dispute::new(1, 1).with_timestamp(1_101)),
+        ];
+
+        for transaction in &blocked_transactions {
+            tm.record_transaction(transaction).unwrap_err();
+            assert!(tm.history.disputed_deposit_mut(1).is_none());
+        }
+
+        tm.record_transaction(&Transaction::Dispute(
+            Dispute::new(1, 1).with_timestamp(1_050),
+        ))
+        .unwrap();
+        assert!(tm.history.disputed_deposit_mut(1).is_some());
+    }
+
+    #[test]
+    fn test_partial_resolve_and_chargeback() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Dispute(Dispute::new(1, 1).with_amount(40.0)),
+            Transaction::Resolve(Resolve::new(1, 1).with_amount(10.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let client_1_balance = ClientBalance::new(70.0, 30.0, 100.0, false, HashSet::from([1]));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        assert_eq!(
+            tm.retrieve_client_balances(),
+            ClientBalanceRegistry::load_registry(internal)
+        );
+
+        let blocked_transaction = Transaction::Chargeback(Chargeback::new(1, 1).with_amount(35.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::AmountExceedsHeld {
+                client: 1,
+                tx: 1,
+                requested: 35.0,
+                held: 30.0
+            }
+        );
+
+        tm.record_transaction(&Transaction::Chargeback(Chargeback::new(1, 1)))
+            .unwrap();
+
+        let client_1_balance = ClientBalance::new(70.0, 0.0, 70.0, true, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_simple_transfer() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Transfer(Transfer::new(1, 2, 2, 12.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Transfer(Transfer::new(1, 2, 3, 21.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 3,
+                requested: 21.0,
+                available: 20.0
+            }
+        );
+
+        let client_1_balance = ClientBalance::new(20.0, 0.0, 20.0, false, HashSet::new());
+        let client_2_balance = ClientBalance::new(12.0, 0.0, 12.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_transfer_to_locked_account() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Deposit(Deposit::new(2, 2, 5.0)),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            Transaction::Chargeback(Chargeback::new(2, 2)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Transfer(Transfer::new(1, 2, 3, 10.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::AccountLocked { client: 2, tx: 3 }
+        );
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let client_2_balance = ClientBalance::new(0.0, 0.0, 0.0, true, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_transfer_dispute_chargeback_returns_funds_to_sender() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Transfer(Transfer::new(1, 2, 2, 12.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        // Only the recipient can dispute a transfer
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 2));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::TransactionNotOwnedByClient {
+                client: 1,
+                tx: 2,
+                owner: 2
+            }
+        );
+
+        let transactions = vec![
+            Transaction::Dispute(Dispute::new(2, 2)),
+            Transaction::Chargeback(Chargeback::new(2, 2)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let client_2_balance = ClientBalance::new(0.0, 0.0, 0.0, true, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_fee_and_interest() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Fee(Fee::new(1, 2, 2.0)),
+            Transaction::Interest(Interest::new(1, 3, 0.5)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Fee(Fee::new(1, 4, 31.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 4,
+                requested: 31.0,
+                available: 30.5
+            }
+        );
+
+        // Interest can't be disputed
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 3));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::DisputedTransactionDoesNotExist { client: 1, tx: 3 }
+        );
+
+        let client_1_balance = ClientBalance::new(30.5, 0.0, 30.5, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_fee_schedule_levies_withdrawal_fee() {
+        test_setup();
+
+        let mut tm = TransactionManager::new()
+            .with_fee_schedule(FeeSchedule::new(None, Some(FeeRate::Percentage(10.0))));
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 20.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        // The fee has to be covered as well
+        let blocked_transaction = Transaction::Withdrawal(Withdrawal::new(1, 3, 10.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 3,
+                requested: 11.0,
+                available: 10.0
+            }
+        );
+
+        let client_1_balance = ClientBalance::new(10.0, 0.0, 10.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_deposit_fee_kept_on_chargeback() {
+        test_setup();
+
+        let mut tm = TransactionManager::new()
+            .with_fee_schedule(FeeSchedule::new(Some(FeeRate::Flat(2.0)), None));
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 10.0)),
+            Transaction::Deposit(Deposit::new(1, 2, 32.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let client_1_balance = ClientBalance::new(38.0, 0.0, 38.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        assert_eq!(
+            tm.retrieve_client_balances(),
+            ClientBalanceRegistry::load_registry(internal)
+        );
+
+        let transactions = vec![
+            Transaction::Dispute(Dispute::new(1, 2)),
+            Transaction::Chargeback(Chargeback::new(1, 2)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        // Only the first deposit, less its fee, is left
+        let client_1_balance = ClientBalance::new(8.0, 0.0, 8.0, true, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_dispute_and_chargeback_of_deposit_with_fee() {
+        test_setup();
+
+        for policy in [
+            NegativeBalancePolicy::RejectDispute,
+            NegativeBalancePolicy::AllowNegative,
+            NegativeBalancePolicy::CapHoldAtAvailable,
+        ] {
+            let mut tm = TransactionManager::new()
+                .with_fee_schedule(FeeSchedule::new(Some(FeeRate::Flat(1.0)), None))
+                .with_negative_balance_policy(policy);
+
+            let transactions = vec![
+                Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+                Transaction::Dispute(Dispute::new(1, 1)),
+            ];
+
+            for transaction in &transactions {
+                tm.record_transaction(transaction).unwrap();
+            }
+
+            // Only what was credited is held
+            let client_1_balance = ClientBalance::new(0.0, 99.0, 99.0, false, HashSet::from([1]));
+            let internal = HashMap::from([(1, client_1_balance)]);
+            assert_eq!(
+                tm.retrieve_client_balances(),
+                ClientBalanceRegistry::load_registry(internal),
+                "{policy:?}"
+            );
+
+            tm.record_transaction(&Transaction::Chargeback(Chargeback::new(1, 1)))
+                .unwrap();
+
+            let client_1_balance = ClientBalance::new(0.0, 0.0, 0.0, true, HashSet::new());
+            let internal = HashMap::from([(1, client_1_balance)]);
+            assert_eq!(
+                tm.retrieve_client_balances(),
+                ClientBalanceRegistry::load_registry(internal),
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_balances_kept_per_currency() {
+        test_setup();
+
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0).with_currency(eur)),
+            Transaction::Deposit(Deposit::new(1, 2, 10.0).with_currency(usd)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 2.0).with_currency(eur)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        // The EUR funds don't cover a USD withdrawal
+        let blocked_transaction =
+            Transaction::Withdrawal(Withdrawal::new(1, 4, 20.0).with_currency(usd));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 4,
+                requested: 20.0,
+                available: 10.0
+            }
+        );
+
+        // The dispute holds USD, since that's what was deposited
+        tm.record_transaction(&Transaction::Dispute(Dispute::new(1, 2)))
+            .unwrap();
+
+        let client_1_balance = ClientBalance {
+            funds: Default::default(),
+            locked: false,
+            disputed_transactions: HashSet::from([2]),
+            ..Default::default()
+        }
+        .with_funds(Some(eur), Funds::new(30.0, 0.0, 30.0))
+        .with_funds(Some(usd), Funds::new(0.0, 10.0, 10.0));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+        assert_eq!(
+            actual_balance.to_csv(),
+            "client,currency,available,held,total,locked\n1,EUR,30,0,30,false\n1,USD,0,10,10,false\n"
+        );
+    }
+
+    #[test]
+    fn test_exchange_between_currencies() {
+        test_setup();
+
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let gbp: Currency = "GBP".parse().unwrap();
+
+        let mut tm = TransactionManager::new()
+            .with_rate_provider(StaticRates::new().with_rate(eur, usd, 1.25));
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 32.0).with_currency(eur)),
+            Transaction::Exchange(Exchange::new(1, 2, 8.0, eur, usd)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        assert_eq!(tm.history().exchange_rate(2), Some(1.25));
+
+        let blocked_transaction = Transaction::Exchange(Exchange::new(1, 3, 1.0, eur, gbp));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::NoExchangeRate {
+                client: 1,
+                tx: 3,
+                from: eur,
+                to: gbp
+            }
+        );
+
+        let blocked_transaction = Transaction::Exchange(Exchange::new(1, 4, 25.0, eur, usd));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 4,
+                requested: 25.0,
+                available: 24.0
+            }
+        );
+
+        let client_1_balance = ClientBalance {
+            funds: Default::default(),
+            locked: false,
+            disputed_transactions: HashSet::new(),
+            ..Default::default()
+        }
+        .with_funds(Some(eur), Funds::new(24.0, 0.0, 24.0))
+        .with_funds(Some(usd), Funds::new(10.0, 0.0, 10.0));
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_overdraft_limit() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+        tm.set_overdraft_limit(2, 5.0).unwrap();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 10.0)),
+            Transaction::OverdraftLimit(OverdraftLimit::new(1, 2, 20.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 25.0)),
+            Transaction::Withdrawal(Withdrawal::new(2, 4, 5.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Withdrawal(Withdrawal::new(1, 5, 10.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 5,
+                requested: 10.0,
+                available: 5.0
+            }
+        );
+
+        let blocked_transaction = Transaction::Fee(Fee::new(2, 6, 1.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 2,
+                tx: 6,
+                requested: 1.0,
+                available: 0.0
+            }
+        );
+
+        assert_eq!(
+            tm.set_overdraft_limit(2, -1.0).unwrap_err(),
+            TransactionManagerError::NegativeOverdraftLimit {
+                client: 2,
+                limit: -1.0
+            }
+        );
+
+        let client_1_balance =
+            ClientBalance::new(-15.0, 0.0, -15.0, false, HashSet::new()).with_overdraft_limit(20.0);
+        let client_2_balance =
+            ClientBalance::new(-5.0, 0.0, -5.0, false, HashSet::new()).with_overdraft_limit(5.0);
+        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_overdraft_limit_only_applies_without_currency() {
+        test_setup();
+
+        let eur: Currency = "EUR".parse().unwrap();
+
+        let mut tm = TransactionManager::new();
+        tm.set_overdraft_limit(1, 10.0).unwrap();
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 5.0).with_currency(eur)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 10.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction =
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 8.0).with_currency(eur));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 3,
+                requested: 8.0,
+                available: 5.0
+            }
+        );
+
+        let blocked_transaction = Transaction::Withdrawal(Withdrawal::new(1, 4, 1.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 4,
+                requested: 1.0,
+                available: 0.0
+            }
+        );
+
+        let client_1_balance = ClientBalance::new(-10.0, 0.0, -10.0, false, HashSet::new())
+            .with_funds(Some(eur), Funds::new(5.0, 0.0, 5.0))
+            .with_overdraft_limit(10.0);
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        let actual_balance = tm.retrieve_client_balances();
+
+        assert_eq!(actual_balance, expected_balances);
+    }
+
+    #[test]
+    fn test_dispute_beyond_overdraft_limit() {
+        test_setup();
+
+        let mut tm = TransactionManager::new();
+
+        let transactions = vec![
+            Transaction::OverdraftLimit(OverdraftLimit::new(1, 1, 10.0)),
+            Transaction::Deposit(Deposit::new(1, 2, 20.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 25.0)),
+            // Holding the deposit takes the client 15 beyond their limit, which is allowed
+            Transaction::Dispute(Dispute::new(1, 2)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        // Though no more funds can be taken out until they're back within it
+        let blocked_transaction = Transaction::Withdrawal(Withdrawal::new(1, 4, 1.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 4,
+                requested: 1.0,
+                available: -15.0
+            }
+        );
+
+        tm.record_transaction(&Transaction::Chargeback(Chargeback::new(1, 2)))
+            .unwrap();
+
+        let client_1_balance =
+            ClientBalance::new(-25.0, 0.0, -25.0, true, HashSet::new()).with_overdraft_limit(10.0);
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
-    #[test]
-    fn test_rejecting_duplicate_transaction_ids() {}
+        let actual_balance = tm.retrieve_client_balances();
 
-    #[test]
-    fn test_negative_amount_deposit() {}
+        assert_eq!(actual_balance, expected_balances);
+    }
 
     #[test]
-    fn test_simple_deposit_withdrawal() {
+    fn test_dispute_of_withdrawn_deposit_allowed_negative() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let mut tm = TransactionManager::new()
+            .with_negative_balance_policy(NegativeBalancePolicy::AllowNegative);
 
-        let deposit = Transaction::Deposit(Deposit::new(1, 1, 32.0));
-        let withdrawal = Transaction::Withdrawal(Withdrawal::new(1, 2, 20.0));
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 100.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+        ];
 
-        tm.record_transaction(&deposit).unwrap();
-        tm.record_transaction(&withdrawal).unwrap();
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
 
-        let mut internal = HashMap::new();
-        let client_1_balance = ClientBalance::new(12.0, 0.0, 12.0, false, HashSet::new());
-        internal.insert(1, client_1_balance);
+        let client_1_balance = ClientBalance::new(-100.0, 100.0, 0.0, false, HashSet::from([1]));
+        let internal = HashMap::from([(1, client_1_balance)]);
         let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
         let actual_balance = tm.retrieve_client_balances();
@@ -346,27 +3152,39 @@ mod tests {
         assert_eq!(actual_balance, expected_balances);
     }
 
-    // TODO: Using test-case would probably simplify this by letting us have various
-    // setups which differ only in the expected input and output
     #[test]
-    fn test_multiple_clients_deposit_withdrawal() {
+    fn test_dispute_of_withdrawn_deposit_rejected() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let mut tm = TransactionManager::new()
+            .with_negative_balance_policy(NegativeBalancePolicy::RejectDispute);
 
         let transactions = vec![
-            Transaction::Deposit(Deposit::new(2, 3, 2.0)),
-            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
-            Transaction::Withdrawal(Withdrawal::new(2, 4, 1.0)),
-            Transaction::Withdrawal(Withdrawal::new(1, 2, 20.0)),
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Deposit(Deposit::new(1, 2, 50.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 100.0)),
+            // Still fully covered by what's available
+            Transaction::Dispute(Dispute::new(1, 2)),
         ];
 
         for transaction in &transactions {
             tm.record_transaction(transaction).unwrap();
         }
-        let client_1_balance = ClientBalance::new(12.0, 0.0, 12.0, false, HashSet::new());
-        let client_2_balance = ClientBalance::new(1.0, 0.0, 1.0, false, HashSet::new());
-        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance)]);
+
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 1));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::DisputeWouldOverdraw {
+                client: 1,
+                tx: 1,
+                requested: 100.0,
+                available: 0.0
+            }
+        );
+
+        let client_1_balance = ClientBalance::new(0.0, 50.0, 50.0, false, HashSet::from([2]));
+        let internal = HashMap::from([(1, client_1_balance)]);
         let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
         let actual_balance = tm.retrieve_client_balances();
@@ -375,19 +3193,27 @@ mod tests {
     }
 
     #[test]
-    fn test_insufficient_funds_for_withdrawal_first_operation() {
+    fn test_dispute_of_zero_amount_rejected() {
         test_setup();
 
         let mut tm = TransactionManager::new();
 
-        let transaction = Transaction::Withdrawal(Withdrawal::new(2, 4, 200.0));
-
-        let err = tm.record_transaction(&transaction).unwrap_err();
-        assert_eq!(err, TransactionManagerError::InsufficientFunds(200.0));
+        tm.record_transaction(&Transaction::Deposit(Deposit::new(1, 1, 100.0)))
+            .unwrap();
 
-        let client_2_balance = ClientBalance::new(0.0, 0.0, 0.0, false, HashSet::new());
-        let internal = HashMap::from([(2, client_2_balance)]);
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 1).with_amount(0.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InvalidTransaction {
+                client: 1,
+                tx: 1,
+                reason: "Disputed amount must be positive".to_string()
+            }
+        );
 
+        let client_1_balance = ClientBalance::new(100.0, 0.0, 100.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
         let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
         let actual_balance = tm.retrieve_client_balances();
@@ -396,13 +3222,16 @@ mod tests {
     }
 
     #[test]
-    fn test_simple_dispute() {
+    fn test_dispute_of_withdrawn_deposit_capped() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let mut tm = TransactionManager::new()
+            .with_negative_balance_policy(NegativeBalancePolicy::CapHoldAtAvailable);
 
         let transactions = vec![
-            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 70.0)),
+            // Only the 30 still available is held
             Transaction::Dispute(Dispute::new(1, 1)),
         ];
 
@@ -410,33 +3239,103 @@ mod tests {
             tm.record_transaction(transaction).unwrap();
         }
 
-        let client_1_balance = ClientBalance::new(0.0, 32.0, 32.0, false, HashSet::from([1]));
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 1));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::NoFundsAvailableToHold { client: 1, tx: 1 }
+        );
+
+        // The rest of the deposit can be disputed once there are funds to hold again
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 3, 20.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(1, 1)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let client_1_balance = ClientBalance::new(0.0, 0.0, 0.0, true, HashSet::new());
         let internal = HashMap::from([(1, client_1_balance)]);
         let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
         let actual_balance = tm.retrieve_client_balances();
 
         assert_eq!(actual_balance, expected_balances);
+        assert_eq!(
+            tm.history().deposit(1).map(|dep| dep.state),
+            Some(TransactionState::ChargedBack)
+        );
     }
 
     #[test]
-    fn test_simple_dispute_resolve() {
+    fn test_rules_reject_hold_and_alert() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let rules = RulesEngine::new()
+            .with_rule(Rule::new(
+                Condition::MaxSingleWithdrawal { amount: 50.0 },
+                RuleAction::Reject,
+            ))
+            .with_rule(Rule::new(
+                Condition::MaxSingleWithdrawal { amount: 20.0 },
+                RuleAction::Hold,
+            ))
+            .with_rule(Rule::new(
+                Condition::DepositThenFullWithdrawal,
+                RuleAction::Alert,
+            ));
+        let mut tm = TransactionManager::new().with_rules(rules);
 
         let transactions = vec![
-            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
-            Transaction::Dispute(Dispute::new(1, 1)),
-            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Deposit(Deposit::new(2, 2, 10.0)),
+            Transaction::Withdrawal(Withdrawal::new(2, 3, 10.0)),
         ];
 
         for transaction in &transactions {
             tm.record_transaction(transaction).unwrap();
         }
 
-        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
-        let internal = HashMap::from([(1, client_1_balance)]);
+        let blocked_transaction = Transaction::Withdrawal(Withdrawal::new(1, 4, 60.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::RejectedByRule {
+                client: 1,
+                tx: 4,
+                rule: "max_single_withdrawal(50)".to_string()
+            }
+        );
+
+        let held_transaction = Transaction::Withdrawal(Withdrawal::new(1, 5, 30.0));
+        let err = tm.record_transaction(&held_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::HeldForReview {
+                client: 1,
+                tx: 5,
+                rule: "max_single_withdrawal(20)".to_string()
+            }
+        );
+        assert_eq!(tm.history().pending().len(), 1);
+
+        let actions: Vec<RuleAction> = tm.rule_matches().iter().map(|m| m.action).collect();
+        assert_eq!(
+            actions,
+            vec![
+                RuleAction::Alert,
+                RuleAction::Reject,
+                RuleAction::Hold,
+                RuleAction::Hold
+            ]
+        );
+
+        let client_1_balance = ClientBalance::new(100.0, 0.0, 100.0, false, HashSet::new());
+        let client_2_balance = ClientBalance::new(0.0, 0.0, 0.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance), (2, client_2_balance)]);
         let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
         let actual_balance = tm.retrieve_client_balances();
@@ -445,22 +3344,81 @@ mod tests {
     }
 
     #[test]
-    fn test_simple_dispute_chargeback() {
+    fn test_approve_and_decline_held_transactions() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let rules = RulesEngine::new().with_rule(Rule::new(
+            Condition::MaxSingleWithdrawal { amount: 20.0 },
+            RuleAction::Hold,
+        ));
+        let mut tm = TransactionManager::new().with_rules(rules);
+
+        tm.record_transaction(&Transaction::Deposit(Deposit::new(1, 1, 100.0)))
+            .unwrap();
+
+        for held in [
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 30.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 40.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 4, 90.0)),
+        ] {
+            let err = tm.record_transaction(&held).unwrap_err();
+            assert_eq!(
+                err,
+                TransactionManagerError::HeldForReview {
+                    client: 1,
+                    tx: held.tx(),
+                    rule: "max_single_withdrawal(20)".to_string()
+                }
+            );
+        }
+
+        let err = tm
+            .record_transaction(&Transaction::Approve(Approve::new(2, 2)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::TransactionNotOwnedByClient {
+                client: 2,
+                tx: 2,
+                owner: 1
+            }
+        );
 
         let transactions = vec![
-            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
-            Transaction::Dispute(Dispute::new(1, 1)),
-            Transaction::Chargeback(Chargeback::new(1, 1)),
+            Transaction::Approve(Approve::new(1, 2)),
+            Transaction::Decline(Decline::new(1, 3)),
         ];
 
         for transaction in &transactions {
             tm.record_transaction(transaction).unwrap();
         }
 
-        let client_1_balance = ClientBalance::new(0.0, 0.0, 0.0, true, HashSet::new());
+        let err = tm
+            .record_transaction(&Transaction::Approve(Approve::new(1, 3)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::NoPendingTransaction { client: 1, tx: 3 }
+        );
+
+        // Approving something which can no longer be applied leaves it held
+        let err = tm
+            .record_transaction(&Transaction::Approve(Approve::new(1, 4)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 4,
+                requested: 90.0,
+                available: 70.0
+            }
+        );
+
+        let pending: Vec<u32> = tm.history().pending().iter().map(|t| t.tx()).collect();
+        assert_eq!(pending, vec![4]);
+
+        let client_1_balance = ClientBalance::new(70.0, 0.0, 70.0, false, HashSet::new());
         let internal = HashMap::from([(1, client_1_balance)]);
         let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
@@ -469,14 +3427,152 @@ mod tests {
         assert_eq!(actual_balance, expected_balances);
     }
 
+    #[cfg(target_os = "linux")]
     #[test]
-    fn test_chargeback_blocks_transactions() {
+    fn test_approve_not_repeated_after_idempotency_index_write_fails() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let rules = RulesEngine::new().with_rule(Rule::new(
+            Condition::MaxSingleWithdrawal { amount: 20.0 },
+            RuleAction::Hold,
+        ));
+        let full = std::fs::OpenOptions::new()
+            .append(true)
+            .open("/dev/full")
+            .unwrap();
+        let mut tm = TransactionManager::new()
+            .with_rules(rules)
+            .with_idempotency_index(IdempotencyIndex::with_journal(full));
+
+        let err = tm
+            .record_transaction(&Transaction::Deposit(Deposit::new(1, 1, 100.0)))
+            .unwrap_err();
+        assert_eq!(err.code(), "idempotency_index_write_failed");
+        let err = tm
+            .record_transaction(&Transaction::Withdrawal(Withdrawal::new(1, 2, 30.0)))
+            .unwrap_err();
+        assert_eq!(err.code(), "held_for_review");
+
+        let approve = Transaction::Approve(Approve::new(1, 2));
+        let err = tm.record_transaction(&approve).unwrap_err();
+        assert_eq!(err.code(), "idempotency_index_write_failed");
+
+        // Approved all the same, so it can't be approved again
+        let err = tm.record_transaction(&approve).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::NoPendingTransaction { client: 1, tx: 2 }
+        );
+
+        let client_1_balance = ClientBalance::new(70.0, 0.0, 70.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        assert_eq!(tm.retrieve_client_balances(), expected_balances);
+    }
+
+    #[test]
+    fn test_held_transaction_id_is_reserved() {
+        test_setup();
+
+        let rules = RulesEngine::new().with_rule(Rule::new(
+            Condition::MaxSingleWithdrawal { amount: 20.0 },
+            RuleAction::Hold,
+        ));
+        let mut tm = TransactionManager::new().with_rules(rules);
+
+        tm.record_transaction(&Transaction::Deposit(Deposit::new(1, 1, 100.0)))
+            .unwrap();
+        tm.record_transaction(&Transaction::Withdrawal(Withdrawal::new(1, 2, 30.0)))
+            .unwrap_err();
+
+        for reused in [
+            Transaction::Deposit(Deposit::new(1, 2, 5.0)),
+            Transaction::Withdrawal(Withdrawal::new(2, 2, 50.0)),
+        ] {
+            let err = tm.record_transaction(&reused).unwrap_err();
+            assert_eq!(
+                err,
+                TransactionManagerError::DuplicateTransactionId {
+                    client: reused.client(),
+                    tx: 2
+                }
+            );
+        }
+
+        tm.record_transaction(&Transaction::Approve(Approve::new(1, 2)))
+            .unwrap();
+        assert!(tm.history().pending().is_empty());
+
+        let err = tm
+            .record_transaction(&Transaction::Deposit(Deposit::new(1, 2, 5.0)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::DuplicateTransactionId { client: 1, tx: 2 }
+        );
+
+        let client_1_balance = ClientBalance::new(70.0, 0.0, 70.0, false, HashSet::new());
+        let internal = HashMap::from([(1, client_1_balance)]);
+        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+
+        assert_eq!(tm.retrieve_client_balances(), expected_balances);
+    }
+
+    #[test]
+    fn test_validations_apply_to_every_type() {
+        test_setup();
+
+        let rules = RulesEngine::new().with_rule(Rule::new(
+            Condition::MaxSingleWithdrawal { amount: 20.0 },
+            RuleAction::Hold,
+        ));
+        let mut tm = TransactionManager::new().with_rules(rules);
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Deposit(Deposit::new(2, 2, 10.0)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let blocked_transaction = Transaction::Dispute(Dispute::new(1, 1).with_amount(-5.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::NegativeAmountNotAllowed {
+                client: 1,
+                tx: 1,
+                amount: -5.0
+            }
+        );
+
+        let blocked_transaction = Transaction::Resolve(Resolve::new(2, 1));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::TransactionNotOwnedByClient {
+                client: 2,
+                tx: 1,
+                owner: 1
+            }
+        );
+
+        // An invalid transaction isn't held, it's rejected outright
+        let blocked_transaction = Transaction::Withdrawal(Withdrawal::new(1, 2, 30.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::DuplicateTransactionId { client: 1, tx: 2 }
+        );
+        assert!(tm.history().pending().is_empty());
+
+        let held_transaction = Transaction::Withdrawal(Withdrawal::new(1, 3, 30.0));
+        tm.record_transaction(&held_transaction).unwrap_err();
 
         let transactions = vec![
-            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
             Transaction::Dispute(Dispute::new(1, 1)),
             Transaction::Chargeback(Chargeback::new(1, 1)),
         ];
@@ -485,38 +3581,86 @@ mod tests {
             tm.record_transaction(transaction).unwrap();
         }
 
-        let blocked_transaction = Transaction::Deposit(Deposit::new(1, 2, 42.0));
-        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        // Held transactions are validated again once approved
+        let err = tm
+            .record_transaction(&Transaction::Approve(Approve::new(1, 3)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::AccountLocked { client: 1, tx: 3 }
+        );
+        assert_eq!(tm.history().pending().len(), 1);
+
+        // Though the held transaction can still be declined
+        tm.record_transaction(&Transaction::Decline(Decline::new(1, 3)))
+            .unwrap();
+        assert!(tm.history().pending().is_empty());
+    }
 
-        assert_eq!(err, TransactionManagerError::AccountLocked);
+    // Withdrawals may only take out half of what the client has, in any one currency
+    struct HalfOfBalance;
 
-        let client_1_balance = ClientBalance::new(0.0, 0.0, 0.0, true, HashSet::new());
-        let internal = HashMap::from([(1, client_1_balance)]);
-        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+    impl TransactionValidator for HalfOfBalance {
+        fn name(&self) -> &str {
+            "half_of_balance"
+        }
 
-        let actual_balance = tm.retrieve_client_balances();
+        fn validate(&self, t: &Transaction, ledger: &LedgerView) -> Result<(), String> {
+            let Transaction::Withdrawal(w) = t else {
+                return Ok(());
+            };
 
-        assert_eq!(actual_balance, expected_balances);
+            let available = ledger
+                .balance
+                .and_then(|balance| balance.funds.get(&w.currency))
+                .map_or(0.0, |funds| funds.available);
+
+            if w.amount > available / 2.0 {
+                return Err(format!("only {} may be withdrawn", available / 2.0));
+            }
+
+            Ok(())
+        }
     }
 
     #[test]
-    fn test_block_duplicated_transactions() {
+    fn test_custom_validators() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let mut tm = TransactionManager::new()
+            .with_validator(BlockedClients::new().with_client(3))
+            .with_validator(HalfOfBalance);
 
-        let transactions = vec![Transaction::Deposit(Deposit::new(1, 1, 32.0))];
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 50.0)),
+        ];
 
         for transaction in &transactions {
             tm.record_transaction(transaction).unwrap();
         }
 
-        let blocked_transaction = Transaction::Deposit(Deposit::new(1, 1, 42.0));
+        let blocked_transaction = Transaction::Withdrawal(Withdrawal::new(1, 3, 30.0));
         let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::RejectedByValidator {
+                client: 1,
+                tx: 3,
+                validator: "half_of_balance".to_string(),
+                reason: "only 25 may be withdrawn".to_string()
+            }
+        );
 
-        assert_eq!(err, TransactionManagerError::DuplicateTransactionId(1));
+        let blocked_transaction = Transaction::Transfer(Transfer::new(1, 3, 4, 10.0));
+        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        assert_eq!(err.code(), "rejected_by_validator");
+        assert_eq!(
+            err.to_string(),
+            "[rejected_by_validator] Tx 4 of client 1 rejected by validator blocked_clients: client 3 is blocked"
+        );
 
-        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let client_1_balance = ClientBalance::new(50.0, 0.0, 50.0, false, HashSet::new());
         let internal = HashMap::from([(1, client_1_balance)]);
         let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
@@ -526,50 +3670,230 @@ mod tests {
     }
 
     #[test]
-    fn test_block_negative_amount() {
+    fn test_simulate_leaves_state_alone() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let mut tm = TransactionManager::new().with_idempotency_index(IdempotencyIndex::new());
 
-        let transactions = vec![Transaction::Deposit(Deposit::new(1, 1, 32.0))];
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Deposit(Deposit::new(2, 2, 10.0)),
+        ];
 
         for transaction in &transactions {
             tm.record_transaction(transaction).unwrap();
         }
 
-        let blocked_transaction = Transaction::Deposit(Deposit::new(1, 2, -42.0));
-        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
+        let before = tm.retrieve_client_balances();
 
-        assert_eq!(err, TransactionManagerError::NegativeAmountNotAllowed);
+        let corrections = vec![
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 40.0)),
+            Transaction::Withdrawal(Withdrawal::new(2, 4, 20.0)),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            Transaction::Chargeback(Chargeback::new(2, 2)),
+            Transaction::Deposit(Deposit::new(3, 5, 5.0)),
+        ];
 
-        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
-        let internal = HashMap::from([(1, client_1_balance)]);
-        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+        let simulation = tm.simulate(&corrections);
+
+        assert_eq!(
+            simulation.rejections,
+            vec![Rejection {
+                index: 1,
+                error: TransactionManagerError::InsufficientFunds {
+                    client: 2,
+                    tx: 4,
+                    requested: 20.0,
+                    available: 10.0
+                }
+            }]
+        );
+        assert_eq!(
+            simulation.to_csv(),
+            "client,available_before,available_after,held_before,held_after,total_before,total_after,locked_before,locked_after\n\
+             1,100,60,0,0,100,60,false,false\n\
+             2,10,0,0,0,10,0,false,true\n\
+             3,0,5,0,0,0,5,false,false\n"
+        );
+
+        assert_eq!(tm.retrieve_client_balances(), before);
+        assert_eq!(tm.run_stats().processed(), 2);
+
+        // Nothing the simulation applied counts as already applied either, so recording
+        // the corrections for real does just what the simulation said it would
+        let rejections: Vec<Rejection> = corrections
+            .iter()
+            .enumerate()
+            .filter_map(|(index, t)| {
+                tm.record_transaction(t)
+                    .err()
+                    .map(|error| Rejection { index, error })
+            })
+            .collect();
+        assert_eq!(rejections, simulation.rejections);
+        assert_eq!(
+            Simulation::new(&before, &tm.retrieve_client_balances(), Vec::new()).deltas,
+            simulation.deltas
+        );
+    }
 
-        let actual_balance = tm.retrieve_client_balances();
+    #[test]
+    fn test_simulate_copies_whatever_the_transactions_reach() {
+        test_setup();
 
-        assert_eq!(actual_balance, expected_balances);
+        let rules = RulesEngine::new().with_rule(Rule::new(
+            Condition::MaxSingleWithdrawal { amount: 20.0 },
+            RuleAction::Hold,
+        ));
+        let mut tm = TransactionManager::new()
+            .with_dispute_policy(DisputePolicy::new(None, Some(50)))
+            .with_rules(rules)
+            .with_idempotency_index(IdempotencyIndex::new());
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0).with_timestamp(1_000)),
+            Transaction::Transfer(Transfer::new(1, 2, 2, 30.0).with_timestamp(1_000)),
+            Transaction::Deposit(Deposit::new(3, 3, 10.0).with_timestamp(1_000)),
+            Transaction::Dispute(Dispute::new(3, 3).with_timestamp(1_010)),
+            Transaction::Deposit(Deposit::new(4, 4, 50.0).with_timestamp(1_010)),
+            Transaction::Deposit(Deposit::new(5, 6, 1.0).with_timestamp(1_010)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+        tm.record_transaction(&Transaction::Withdrawal(
+            Withdrawal::new(4, 5, 30.0).with_timestamp(1_010),
+        ))
+        .unwrap_err();
+
+        let before = tm.retrieve_client_balances();
+
+        // None of them mention client 1 or 3, or tx 5's withdrawal, yet they all change
+        let corrections = vec![
+            Transaction::Dispute(Dispute::new(2, 2).with_timestamp(1_020)),
+            Transaction::Chargeback(Chargeback::new(2, 2).with_timestamp(1_020)),
+            Transaction::Deposit(Deposit::new(6, 1, 5.0).with_timestamp(1_020)),
+            Transaction::Approve(Approve::new(4, 5).with_timestamp(1_020)),
+            Transaction::Deposit(Deposit::new(6, 7, 1.0).with_timestamp(1_070)),
+        ];
+
+        let simulation = tm.simulate(&corrections);
+
+        assert_eq!(
+            simulation.rejections,
+            vec![Rejection {
+                index: 2,
+                error: TransactionManagerError::DuplicateTransactionId { client: 6, tx: 1 }
+            }]
+        );
+        assert_eq!(
+            simulation.to_csv(),
+            "client,available_before,available_after,held_before,held_after,total_before,total_after,locked_before,locked_after\n\
+             1,70,100,0,0,70,100,false,false\n\
+             2,30,0,0,0,30,0,false,true\n\
+             3,0,10,10,0,10,10,false,false\n\
+             4,50,20,0,0,50,20,false,false\n\
+             6,0,1,0,0,0,1,false,false\n"
+        );
+
+        let rejections: Vec<Rejection> = corrections
+            .iter()
+            .enumerate()
+            .filter_map(|(index, t)| {
+                tm.record_transaction(t)
+                    .err()
+                    .map(|error| Rejection { index, error })
+            })
+            .collect();
+        assert_eq!(rejections, simulation.rejections);
+        assert_eq!(
+            Simulation::new(&before, &tm.retrieve_client_balances(), Vec::new()).deltas,
+            simulation.deltas
+        );
     }
 
     #[test]
-    fn test_resolve_dispute_that_doesnt_exist() {
+    fn test_reversal_of_deposits_and_withdrawals() {
         test_setup();
 
-        let mut tm = TransactionManager::new();
+        let mut tm = TransactionManager::new()
+            .with_fee_schedule(FeeSchedule::new(None, Some(FeeRate::Flat(1.0))));
 
         let transactions = vec![
-            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Deposit(Deposit::new(1, 1, 100.0)),
+            Transaction::Deposit(Deposit::new(1, 2, 50.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 20.0)),
+            Transaction::Reversal(Reversal::new(1, 3)),
+            Transaction::Reversal(Reversal::new(1, 2)),
         ];
 
         for transaction in &transactions {
             tm.record_transaction(transaction).unwrap();
         }
 
-        let blocked_transaction = Transaction::Resolve(Resolve::new(1, 1));
-        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
-        assert_eq!(err, TransactionManagerError::NoOpenDispute(1));
+        for (transaction, expected) in [
+            (
+                Transaction::Reversal(Reversal::new(1, 2)),
+                TransactionManagerError::AlreadyReversed { client: 1, tx: 2 },
+            ),
+            (
+                Transaction::Reversal(Reversal::new(1, 3)),
+                TransactionManagerError::AlreadyReversed { client: 1, tx: 3 },
+            ),
+            (
+                Transaction::Dispute(Dispute::new(1, 2)),
+                TransactionManagerError::AlreadyReversed { client: 1, tx: 2 },
+            ),
+            (
+                Transaction::Reversal(Reversal::new(2, 1)),
+                TransactionManagerError::TransactionNotOwnedByClient {
+                    client: 2,
+                    tx: 1,
+                    owner: 1,
+                },
+            ),
+            (
+                Transaction::Reversal(Reversal::new(1, 9)),
+                TransactionManagerError::TransactionNotReversible { client: 1, tx: 9 },
+            ),
+        ] {
+            assert_eq!(tm.record_transaction(&transaction).unwrap_err(), expected);
+        }
 
-        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
+        let transactions = vec![
+            Transaction::Withdrawal(Withdrawal::new(1, 4, 79.0)),
+            Transaction::Deposit(Deposit::new(1, 5, 10.0)),
+            Transaction::Dispute(Dispute::new(1, 5)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        // The first deposit has mostly been withdrawn since
+        let err = tm
+            .record_transaction(&Transaction::Reversal(Reversal::new(1, 1)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::InsufficientFunds {
+                client: 1,
+                tx: 1,
+                requested: 100.0,
+                available: 20.0
+            }
+        );
+
+        let err = tm
+            .record_transaction(&Transaction::Reversal(Reversal::new(1, 5)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionManagerError::ReversalOfDisputedTransaction { client: 1, tx: 5 }
+        );
+
+        let client_1_balance = ClientBalance::new(20.0, 10.0, 30.0, false, HashSet::from([5]));
         let internal = HashMap::from([(1, client_1_balance)]);
         let expected_balances = ClientBalanceRegistry::load_registry(internal);
 
@@ -579,29 +3903,230 @@ mod tests {
     }
 
     #[test]
-    fn test_chargeback_dispute_that_doesnt_exist() {
+    fn test_run_stats() {
         test_setup();
 
         let mut tm = TransactionManager::new();
 
         let transactions = vec![
-            Transaction::Deposit(Deposit::new(1, 1, 32.0)),
+            Transaction::Deposit(Deposit::new(1, 1, 10.0)),
+            Transaction::Deposit(Deposit::new(2, 2, 20.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 5.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 4, 50.0)),
+            Transaction::Dispute(Dispute::new(2, 2).with_amount(8.0)),
+            Transaction::Chargeback(Chargeback::new(2, 2).with_amount(3.0)),
+            Transaction::Deposit(Deposit::new(1, 5, 7.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 6, 2.0)),
+            Transaction::Reversal(Reversal::new(1, 5)),
+            Transaction::Reversal(Reversal::new(1, 6)),
+            Transaction::Reversal(Reversal::new(1, 6)),
+        ];
+
+        for transaction in &transactions {
+            let _ = tm.record_transaction(transaction);
+        }
+
+        let stats = tm.run_stats();
+
+        assert_eq!(stats.processed(), 11);
+        assert_eq!(stats.by_type["reversal"].accepted, 2);
+        assert_eq!(
+            stats.by_type["reversal"].rejected_by_error["already_reversed"],
+            1
+        );
+        assert_eq!(stats.by_type["withdrawal"].accepted, 2);
+        assert_eq!(
+            stats.by_type["withdrawal"].rejected_by_error["insufficient_funds"],
+            1
+        );
+        assert_eq!(stats.clients, 2);
+        assert_eq!(stats.locked_clients, 1);
+        let totals = stats.totals(None);
+        assert_eq!(totals.deposited, 30.0);
+        assert_eq!(totals.withdrawn, 5.0);
+        assert_eq!(totals.held, 5.0);
+        assert_eq!(totals.charged_back, 3.0);
+
+        // Fees on deposits are kept when they're reversed, and those on withdrawals refunded
+        let mut tm = TransactionManager::new().with_fee_schedule(FeeSchedule::new(
+            Some(FeeRate::Flat(1.0)),
+            Some(FeeRate::Flat(0.5)),
+        ));
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 10.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 4.0)),
+            Transaction::Reversal(Reversal::new(1, 2)),
+            Transaction::Reversal(Reversal::new(1, 1)),
         ];
 
         for transaction in &transactions {
             tm.record_transaction(transaction).unwrap();
         }
 
-        let blocked_transaction = Transaction::Chargeback(Chargeback::new(1, 1));
-        let err = tm.record_transaction(&blocked_transaction).unwrap_err();
-        assert_eq!(err, TransactionManagerError::NoOpenDispute(1));
+        let totals = tm.run_stats().totals(None);
+        assert_eq!(totals.deposited, 0.0);
+        assert_eq!(totals.withdrawn, 0.0);
+        assert_eq!(totals.fees, 1.0);
+    }
 
-        let client_1_balance = ClientBalance::new(32.0, 0.0, 32.0, false, HashSet::new());
-        let internal = HashMap::from([(1, client_1_balance)]);
-        let expected_balances = ClientBalanceRegistry::load_registry(internal);
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics_fed_from_record_transaction() {
+        test_setup();
 
-        let actual_balance = tm.retrieve_client_balances();
+        let metrics = Arc::new(Metrics::new());
+        let mut tm = TransactionManager::new().with_metrics(metrics.clone());
 
-        assert_eq!(actual_balance, expected_balances);
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 10.0)),
+            Transaction::Deposit(Deposit::new(2, 2, 10.0)),
+            Transaction::Withdrawal(Withdrawal::new(1, 3, 50.0)),
+            Transaction::Dispute(Dispute::new(1, 1)),
+            Transaction::Dispute(Dispute::new(2, 2)),
+            Transaction::Chargeback(Chargeback::new(2, 2)),
+        ];
+
+        for transaction in &transactions {
+            let _ = tm.record_transaction(transaction);
+        }
+
+        let text = metrics.render();
+        assert!(text.contains("transactions_total{type=\"deposit\",outcome=\"accepted\"} 2\n"));
+        assert!(text.contains(
+            "transactions_total{type=\"withdrawal\",outcome=\"insufficient_funds\"} 1\n"
+        ));
+        assert!(text.contains("transaction_processing_seconds_count 6\n"));
+        assert!(text.contains("open_disputes 1\n"));
+        assert!(text.contains("locked_accounts 1\n"));
+        assert!(text.contains("history_size 2\n"));
+
+        // Resolved, charged back and expired disputes are all closed, other than those on
+        // locked accounts which never expire
+        let metrics = Arc::new(Metrics::new());
+        let mut tm = TransactionManager::new()
+            .with_dispute_policy(DisputePolicy::new(None, Some(100)))
+            .with_metrics(metrics.clone());
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 10.0)),
+            Transaction::Deposit(Deposit::new(1, 2, 10.0)),
+            Transaction::Deposit(Deposit::new(2, 3, 10.0)),
+            Transaction::Deposit(Deposit::new(2, 4, 10.0)),
+            Transaction::Dispute(Dispute::new(1, 1).with_timestamp(10)),
+            Transaction::Dispute(Dispute::new(1, 2).with_timestamp(20)),
+            Transaction::Dispute(Dispute::new(2, 3).with_timestamp(30)),
+            Transaction::Dispute(Dispute::new(2, 4).with_timestamp(40)),
+            Transaction::Resolve(Resolve::new(1, 1)),
+            Transaction::Chargeback(Chargeback::new(2, 3)),
+        ];
+
+        for transaction in &transactions {
+            tm.record_transaction(transaction).unwrap();
+        }
+
+        let text = metrics.render();
+        assert!(text.contains("open_disputes 2\n"));
+        assert!(text.contains("locked_accounts 1\n"));
+
+        tm.record_transaction(&Transaction::Deposit(
+            Deposit::new(3, 5, 1.0).with_timestamp(200),
+        ))
+        .unwrap();
+        assert!(metrics.render().contains("open_disputes 1\n"));
+    }
+
+    #[test]
+    fn test_balances_as_of() {
+        test_setup();
+
+        let available = |registry: &ClientBalanceRegistry, client: u16| {
+            registry.client_balances[&client].funds(None).available
+        };
+
+        let mut tm = TransactionManager::new().with_checkpoints(2);
+        assert_eq!(
+            tm.balances_as_of(AsOf::Sequence(0)),
+            Some(ClientBalanceRegistry::new())
+        );
+
+        let transactions = vec![
+            Transaction::Deposit(Deposit::new(1, 1, 100.0).with_timestamp(10)),
+            Transaction::Withdrawal(Withdrawal::new(1, 2, 30.0).with_timestamp(20)),
+            Transaction::Deposit(Deposit::new(2, 3, 50.0)),
+            Transaction::Withdrawal(Withdrawal::new(2, 4, 80.0).with_timestamp(30)),
+        ];
+
+        for transaction in &transactions {
+            let _ = tm.record_transaction(transaction);
+        }
+
+        // Not a transaction, so it can only be replayed from a checkpoint taken after it
+        tm.set_overdraft_limit(2, 50.0).unwrap();
+        tm.record_transaction(&Transaction::Withdrawal(
+            Withdrawal::new(2, 5, 80.0).with_timestamp(40),
+        ))
+        .unwrap();
+
+        let first = tm.balances_as_of(AsOf::Sequence(1)).unwrap();
+        assert_eq!(available(&first, 1), 100.0);
+        assert!(!first.client_balances.contains_key(&2));
+
+        let third = tm.balances_as_of(AsOf::Sequence(3)).unwrap();
+        assert_eq!(available(&third, 1), 70.0);
+        assert_eq!(available(&third, 2), 50.0);
+
+        // The deposit without a timestamp happened at the time of the withdrawal before it
+        assert_eq!(tm.balances_as_of(AsOf::Timestamp(25)), Some(third));
+        assert_eq!(
+            tm.balances_as_of(AsOf::Timestamp(5)),
+            Some(ClientBalanceRegistry::new())
+        );
+
+        assert_eq!(
+            tm.balances_as_of(AsOf::Sequence(5)),
+            Some(tm.retrieve_client_balances())
+        );
+        assert_eq!(
+            tm.balances_as_of(AsOf::Timestamp(40)),
+            Some(tm.retrieve_client_balances())
+        );
+        assert_eq!(tm.balances_as_of(AsOf::Sequence(6)), None);
+
+        // Replaying doesn't count towards the statistics
+        assert_eq!(tm.run_stats().processed(), 5);
+
+        assert_eq!(
+            TransactionManager::new().balances_as_of(AsOf::Sequence(0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_checkpoints_memory_is_bounded() {
+        test_setup();
+
+        let mut tm = TransactionManager::new().with_checkpoints(10);
+
+        // Other changes are only noted, rather than each taking a copy
+        for client in 1..=1000 {
+            tm.set_overdraft_limit(client, 5.0).unwrap();
+            tm.advance_time(u64::from(client));
+        }
+        for tx in 1..=10_000 {
+            let client = (tx % 1000) as u16 + 1;
+            tm.record_transaction(&Transaction::Deposit(Deposit::new(client, tx, 1.0)))
+                .unwrap();
+        }
+
+        let checkpoints = tm.checkpoints.as_ref().unwrap();
+        // The first copy and those after 10, 20, 40, ..., 5120 transactions
+        assert_eq!(checkpoints.states().count(), 11);
+        let copied: usize = checkpoints.states().map(|state| state.history.len()).sum();
+        assert!(copied <= 2 * tm.history().len());
+
+        let halfway = tm.balances_as_of(AsOf::Sequence(5000)).unwrap();
+        assert_eq!(halfway.client_balances[&1].funds(None).available, 5.0);
+        assert_eq!(halfway.client_balances[&1].overdraft_limit, 5.0);
     }
 }
@@ -1,16 +1,36 @@
+use crate::currency::Currency;
 use serde::de::{self, Deserializer};
 use serde::Deserialize;
+use std::fmt;
 
 #[derive(Clone, Debug)]
 pub struct Deposit {
     pub client: u16,
     pub tx: u32,
     pub amount: f64,
+    pub currency: Option<Currency>,
+    pub timestamp: Option<u64>,
 }
 
 impl Deposit {
     pub fn new(client: u16, tx: u32, amount: f64) -> Self {
-        Self { client, tx, amount }
+        Self {
+            client,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
     }
 }
 
@@ -19,11 +39,190 @@ pub struct Withdrawal {
     pub client: u16,
     pub tx: u32,
     pub amount: f64,
+    pub currency: Option<Currency>,
+    pub timestamp: Option<u64>,
 }
 
 impl Withdrawal {
     pub fn new(client: u16, tx: u32, amount: f64) -> Self {
-        Self { client, tx, amount }
+        Self {
+            client,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Charged to a client, e.g. an account fee. Unlike a withdrawal it isn't subject to the
+/// `FeeSchedule` itself
+#[derive(Clone, Debug)]
+pub struct Fee {
+    pub client: u16,
+    pub tx: u32,
+    pub amount: f64,
+    pub currency: Option<Currency>,
+    pub timestamp: Option<u64>,
+}
+
+impl Fee {
+    pub fn new(client: u16, tx: u32, amount: f64) -> Self {
+        Self {
+            client,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Paid to a client. Unlike a deposit it can't be disputed
+#[derive(Clone, Debug)]
+pub struct Interest {
+    pub client: u16,
+    pub tx: u32,
+    pub amount: f64,
+    pub currency: Option<Currency>,
+    pub timestamp: Option<u64>,
+}
+
+impl Interest {
+    pub fn new(client: u16, tx: u32, amount: f64) -> Self {
+        Self {
+            client,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Converts some of a client's funds from one currency into another, at the rate given
+/// by the `RateProvider` of the `TransactionManager`
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    pub client: u16,
+    pub tx: u32,
+    /// Amount of `from_currency` to convert
+    pub amount: f64,
+    pub from_currency: Currency,
+    pub to_currency: Currency,
+    pub timestamp: Option<u64>,
+}
+
+impl Exchange {
+    pub fn new(
+        client: u16,
+        tx: u32,
+        amount: f64,
+        from_currency: Currency,
+        to_currency: Currency,
+    ) -> Self {
+        Self {
+            client,
+            tx,
+            amount,
+            from_currency,
+            to_currency,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Administrative transaction setting how far below zero a client's available funds may go
+#[derive(Clone, Debug)]
+pub struct OverdraftLimit {
+    pub client: u16,
+    pub tx: u32,
+    pub limit: f64,
+    pub timestamp: Option<u64>,
+}
+
+impl OverdraftLimit {
+    pub fn new(client: u16, tx: u32, limit: f64) -> Self {
+        Self {
+            client,
+            tx,
+            limit,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Moves funds from one client's account to another's
+#[derive(Clone, Debug)]
+pub struct Transfer {
+    pub from_client: u16,
+    pub to_client: u16,
+    pub tx: u32,
+    pub amount: f64,
+    pub currency: Option<Currency>,
+    pub timestamp: Option<u64>,
+}
+
+impl Transfer {
+    pub fn new(from_client: u16, to_client: u16, tx: u32, amount: f64) -> Self {
+        Self {
+            from_client,
+            to_client,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
     }
 }
 
@@ -31,11 +230,29 @@ impl Withdrawal {
 pub struct Dispute {
     pub client: u16,
     pub tx: u32,
+    /// Only dispute this part of the deposit, if set, otherwise all of what's left undisputed
+    pub amount: Option<f64>,
+    pub timestamp: Option<u64>,
 }
 
 impl Dispute {
     pub fn new(client: u16, tx: u32) -> Self {
-        Self { client, tx }
+        Self {
+            client,
+            tx,
+            amount: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_amount(mut self, amount: f64) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
     }
 }
 
@@ -43,11 +260,29 @@ impl Dispute {
 pub struct Resolve {
     pub client: u16,
     pub tx: u32,
+    /// Only release this part of the held amount, if set, otherwise all of it
+    pub amount: Option<f64>,
+    pub timestamp: Option<u64>,
 }
 
 impl Resolve {
     pub fn new(client: u16, tx: u32) -> Self {
-        Self { client, tx }
+        Self {
+            client,
+            tx,
+            amount: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_amount(mut self, amount: f64) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
     }
 }
 
@@ -55,11 +290,173 @@ impl Resolve {
 pub struct Chargeback {
     pub client: u16,
     pub tx: u32,
+    /// Only charge back this part of the held amount, if set, otherwise all of it
+    pub amount: Option<f64>,
+    pub timestamp: Option<u64>,
 }
 
 impl Chargeback {
     pub fn new(client: u16, tx: u32) -> Self {
-        Self { client, tx }
+        Self {
+            client,
+            tx,
+            amount: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_amount(mut self, amount: f64) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Administrative transaction voiding a deposit or withdrawal, e.g. one which was mis-keyed,
+/// as if it had never been made. Unlike a chargeback it doesn't lock the account.
+#[derive(Clone, Debug)]
+pub struct Reversal {
+    pub client: u16,
+    /// Id of the reversed transaction
+    pub tx: u32,
+    pub timestamp: Option<u64>,
+}
+
+impl Reversal {
+    pub fn new(client: u16, tx: u32) -> Self {
+        Self {
+            client,
+            tx,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Administrative transaction applying a transaction which was held for review
+#[derive(Clone, Debug)]
+pub struct Approve {
+    pub client: u16,
+    /// Id of the held transaction
+    pub tx: u32,
+    pub timestamp: Option<u64>,
+}
+
+impl Approve {
+    pub fn new(client: u16, tx: u32) -> Self {
+        Self {
+            client,
+            tx,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Administrative transaction dropping a transaction which was held for review
+#[derive(Clone, Debug)]
+pub struct Decline {
+    pub client: u16,
+    /// Id of the held transaction
+    pub tx: u32,
+    pub timestamp: Option<u64>,
+}
+
+impl Decline {
+    pub fn new(client: u16, tx: u32) -> Self {
+        Self {
+            client,
+            tx,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// The type of a `Transaction`, without anything else about it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+    Transfer,
+    Fee,
+    Interest,
+    Exchange,
+    OverdraftLimit,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Reversal,
+    Approve,
+    Decline,
+}
+
+impl TransactionKind {
+    /// The `type` of the transaction, as given in the CSV
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Transfer => "transfer",
+            TransactionKind::Fee => "fee",
+            TransactionKind::Interest => "interest",
+            TransactionKind::Exchange => "exchange",
+            TransactionKind::OverdraftLimit => "overdraft_limit",
+            TransactionKind::Dispute => "dispute",
+            TransactionKind::Resolve => "resolve",
+            TransactionKind::Chargeback => "chargeback",
+            TransactionKind::Reversal => "reversal",
+            TransactionKind::Approve => "approve",
+            TransactionKind::Decline => "decline",
+        }
+    }
+
+    /// Whether transactions of this kind refer to an earlier transaction by its id, rather
+    /// than having an id of their own
+    pub fn refers_to_earlier(&self) -> bool {
+        matches!(
+            self,
+            TransactionKind::Dispute
+                | TransactionKind::Resolve
+                | TransactionKind::Chargeback
+                | TransactionKind::Reversal
+                | TransactionKind::Approve
+                | TransactionKind::Decline
+        )
+    }
+
+    /// Whether transactions of this kind are made by the operator rather than the client,
+    /// and so are allowed on locked accounts
+    pub fn is_administrative(&self) -> bool {
+        matches!(
+            self,
+            TransactionKind::OverdraftLimit
+                | TransactionKind::Reversal
+                | TransactionKind::Approve
+                | TransactionKind::Decline
+        )
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
     }
 }
 
@@ -67,9 +464,139 @@ impl Chargeback {
 pub enum Transaction {
     Deposit(Deposit),
     Withdrawal(Withdrawal),
+    Transfer(Transfer),
+    Fee(Fee),
+    Interest(Interest),
+    Exchange(Exchange),
+    OverdraftLimit(OverdraftLimit),
     Dispute(Dispute),
     Resolve(Resolve),
     Chargeback(Chargeback),
+    Reversal(Reversal),
+    Approve(Approve),
+    Decline(Decline),
+}
+
+impl Transaction {
+    pub fn kind(&self) -> TransactionKind {
+        match self {
+            Transaction::Deposit(_) => TransactionKind::Deposit,
+            Transaction::Withdrawal(_) => TransactionKind::Withdrawal,
+            Transaction::Transfer(_) => TransactionKind::Transfer,
+            Transaction::Fee(_) => TransactionKind::Fee,
+            Transaction::Interest(_) => TransactionKind::Interest,
+            Transaction::Exchange(_) => TransactionKind::Exchange,
+            Transaction::OverdraftLimit(_) => TransactionKind::OverdraftLimit,
+            Transaction::Dispute(_) => TransactionKind::Dispute,
+            Transaction::Resolve(_) => TransactionKind::Resolve,
+            Transaction::Chargeback(_) => TransactionKind::Chargeback,
+            Transaction::Reversal(_) => TransactionKind::Reversal,
+            Transaction::Approve(_) => TransactionKind::Approve,
+            Transaction::Decline(_) => TransactionKind::Decline,
+        }
+    }
+
+    /// The `type` of the transaction, as given in the CSV
+    pub fn type_name(&self) -> &'static str {
+        self.kind().name()
+    }
+
+    pub fn tx(&self) -> u32 {
+        match self {
+            Transaction::Deposit(d) => d.tx,
+            Transaction::Withdrawal(w) => w.tx,
+            Transaction::Transfer(t) => t.tx,
+            Transaction::Fee(f) => f.tx,
+            Transaction::Interest(i) => i.tx,
+            Transaction::Exchange(e) => e.tx,
+            Transaction::OverdraftLimit(o) => o.tx,
+            Transaction::Dispute(d) => d.tx,
+            Transaction::Resolve(r) => r.tx,
+            Transaction::Chargeback(c) => c.tx,
+            Transaction::Reversal(r) => r.tx,
+            Transaction::Approve(a) => a.tx,
+            Transaction::Decline(d) => d.tx,
+        }
+    }
+
+    /// The client whose account the transaction is on, which for a transfer is the sender
+    pub fn client(&self) -> u16 {
+        match self {
+            Transaction::Deposit(d) => d.client,
+            Transaction::Withdrawal(w) => w.client,
+            Transaction::Transfer(t) => t.from_client,
+            Transaction::Fee(f) => f.client,
+            Transaction::Interest(i) => i.client,
+            Transaction::Exchange(e) => e.client,
+            Transaction::OverdraftLimit(o) => o.client,
+            Transaction::Dispute(d) => d.client,
+            Transaction::Resolve(r) => r.client,
+            Transaction::Chargeback(c) => c.client,
+            Transaction::Reversal(r) => r.client,
+            Transaction::Approve(a) => a.client,
+            Transaction::Decline(d) => d.client,
+        }
+    }
+
+    /// The client credited by a transfer, who is also affected by it
+    pub fn counterparty(&self) -> Option<u16> {
+        match self {
+            Transaction::Transfer(t) => Some(t.to_client),
+            _ => None,
+        }
+    }
+
+    /// The amount in the amount column, if the transaction has one. For an overdraft
+    /// limit that's the limit, and for a partial dispute, resolve or chargeback the part
+    /// of the earlier transaction it's about
+    pub fn amount(&self) -> Option<f64> {
+        match self {
+            Transaction::Deposit(d) => Some(d.amount),
+            Transaction::Withdrawal(w) => Some(w.amount),
+            Transaction::Transfer(t) => Some(t.amount),
+            Transaction::Fee(f) => Some(f.amount),
+            Transaction::Interest(i) => Some(i.amount),
+            Transaction::Exchange(e) => Some(e.amount),
+            Transaction::OverdraftLimit(o) => Some(o.limit),
+            Transaction::Dispute(d) => d.amount,
+            Transaction::Resolve(r) => r.amount,
+            Transaction::Chargeback(c) => c.amount,
+            Transaction::Reversal(_) | Transaction::Approve(_) | Transaction::Decline(_) => None,
+        }
+    }
+
+    /// The currency of the amount, if it's in one. For an exchange that's the currency
+    /// converted from
+    pub fn currency(&self) -> Option<Currency> {
+        match self {
+            Transaction::Deposit(d) => d.currency,
+            Transaction::Withdrawal(w) => w.currency,
+            Transaction::Transfer(t) => t.currency,
+            Transaction::Fee(f) => f.currency,
+            Transaction::Interest(i) => i.currency,
+            Transaction::Exchange(e) => Some(e.from_currency),
+            _ => None,
+        }
+    }
+
+    /// When the transaction happened, as seconds since the Unix epoch, if the input had it
+    pub fn timestamp(&self) -> Option<u64> {
+        match self {
+            Transaction::Deposit(d) => d.timestamp,
+            Transaction::Withdrawal(w) => w.timestamp,
+            Transaction::Transfer(t) => t.timestamp,
+            Transaction::Fee(f) => f.timestamp,
+            Transaction::Interest(i) => i.timestamp,
+            Transaction::Exchange(e) => e.timestamp,
+            Transaction::OverdraftLimit(o) => o.timestamp,
+            Transaction::Dispute(d) => d.timestamp,
+            Transaction::Resolve(r) => r.timestamp,
+            Transaction::Chargeback(c) => c.timestamp,
+            Transaction::Reversal(r) => r.timestamp,
+            Transaction::Approve(a) => a.timestamp,
+            Transaction::Decline(d) => d.timestamp,
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for Transaction {
@@ -85,6 +612,18 @@ impl<'de> Deserialize<'de> for Transaction {
             tx: u32,
             // Optional, since not all types use the transaction amount
             amount: Option<f64>,
+            // Optional, since only transfers have a receiving client
+            #[serde(default)]
+            to_client: Option<u16>,
+            // Optional, since funds don't need to be in any specific currency
+            #[serde(default)]
+            currency: Option<Currency>,
+            // Optional, since only exchanges convert to another currency
+            #[serde(default)]
+            to_currency: Option<Currency>,
+            // Optional, seconds since the Unix epoch
+            #[serde(default)]
+            timestamp: Option<u64>,
         }
 
         let record = TransactionRecord::deserialize(deserializer)?;
@@ -96,6 +635,8 @@ impl<'de> Deserialize<'de> for Transaction {
                         client: record.client,
                         tx: record.tx,
                         amount,
+                        currency: record.currency,
+                        timestamp: record.timestamp,
                     }))
                 } else {
                     Err(de::Error::custom("Missing amount for deposit"))
@@ -107,22 +648,120 @@ impl<'de> Deserialize<'de> for Transaction {
                         client: record.client,
                         tx: record.tx,
                         amount,
+                        currency: record.currency,
+                        timestamp: record.timestamp,
                     }))
                 } else {
                     Err(de::Error::custom("Missing amount for withdrawal"))
                 }
             }
+            "fee" => {
+                if let Some(amount) = record.amount {
+                    Ok(Transaction::Fee(Fee {
+                        client: record.client,
+                        tx: record.tx,
+                        amount,
+                        currency: record.currency,
+                        timestamp: record.timestamp,
+                    }))
+                } else {
+                    Err(de::Error::custom("Missing amount for fee"))
+                }
+            }
+            "interest" => {
+                if let Some(amount) = record.amount {
+                    Ok(Transaction::Interest(Interest {
+                        client: record.client,
+                        tx: record.tx,
+                        amount,
+                        currency: record.currency,
+                        timestamp: record.timestamp,
+                    }))
+                } else {
+                    Err(de::Error::custom("Missing amount for interest"))
+                }
+            }
+            "exchange" => {
+                let Some(amount) = record.amount else {
+                    return Err(de::Error::custom("Missing amount for exchange"));
+                };
+                let Some(from_currency) = record.currency else {
+                    return Err(de::Error::custom("Missing currency for exchange"));
+                };
+                let Some(to_currency) = record.to_currency else {
+                    return Err(de::Error::custom("Missing to_currency for exchange"));
+                };
+
+                Ok(Transaction::Exchange(Exchange {
+                    client: record.client,
+                    tx: record.tx,
+                    amount,
+                    from_currency,
+                    to_currency,
+                    timestamp: record.timestamp,
+                }))
+            }
+            "overdraft_limit" => {
+                if let Some(limit) = record.amount {
+                    Ok(Transaction::OverdraftLimit(OverdraftLimit {
+                        client: record.client,
+                        tx: record.tx,
+                        limit,
+                        timestamp: record.timestamp,
+                    }))
+                } else {
+                    Err(de::Error::custom("Missing amount for overdraft_limit"))
+                }
+            }
+            "transfer" => {
+                let Some(amount) = record.amount else {
+                    return Err(de::Error::custom("Missing amount for transfer"));
+                };
+                let Some(to_client) = record.to_client else {
+                    return Err(de::Error::custom("Missing to_client for transfer"));
+                };
+
+                Ok(Transaction::Transfer(Transfer {
+                    from_client: record.client,
+                    to_client,
+                    tx: record.tx,
+                    amount,
+                    currency: record.currency,
+                    timestamp: record.timestamp,
+                }))
+            }
             "dispute" => Ok(Transaction::Dispute(Dispute {
                 client: record.client,
                 tx: record.tx,
+                amount: record.amount,
+                timestamp: record.timestamp,
             })),
             "resolve" => Ok(Transaction::Resolve(Resolve {
                 client: record.client,
                 tx: record.tx,
+                amount: record.amount,
+                timestamp: record.timestamp,
             })),
             "chargeback" => Ok(Transaction::Chargeback(Chargeback {
                 client: record.client,
                 tx: record.tx,
+                amount: record.amount,
+                timestamp: record.timestamp,
+            })),
+            "reversal" => Ok(Transaction::Reversal(Reversal {
+                client: record.client,
+                tx: record.tx,
+                timestamp: record.timestamp,
+            })),
+            "approve" => Ok(Transaction::Approve(Approve {
+                client: record.client,
+                tx: record.tx,
+                timestamp: record.timestamp,
+            })),
+            "decline" => Ok(Transaction::Decline(Decline {
+                client: record.client,
+                tx: record.tx,
+                timestamp: record.timestamp,
             })),
             _ => Err(de::Error::custom("Unknown transaction type")),
         }
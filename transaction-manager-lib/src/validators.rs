@@ -0,0 +1,155 @@
+//! Custom checks transactions have to pass before they're applied, on top of the
+//! manager's own, see `TransactionManager::with_validator`.
+
+use crate::balance::ClientBalance;
+use crate::csv_rows::read_rows;
+use crate::history::TransactionHistory;
+use crate::transactions::Transaction;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// What a validator gets to see of the manager's state, without being able to change it
+#[derive(Clone, Copy, Debug)]
+pub struct LedgerView<'a> {
+    /// Account of the client the transaction is on, if they've been seen before
+    pub balance: Option<&'a ClientBalance>,
+    pub history: &'a TransactionHistory,
+}
+
+/// A check which may reject a transaction. Validators run after the manager's own checks,
+/// so a transaction they're given is never a duplicate, has no negative amount, isn't on
+/// a locked account and isn't about another client's transaction.
+pub trait TransactionValidator: Send + Sync {
+    /// Given in rejections, e.g. `blocked_clients`
+    fn name(&self) -> &str;
+
+    /// Why `t` should be rejected, if it should
+    fn validate(&self, t: &Transaction, ledger: &LedgerView) -> Result<(), String>;
+}
+
+/// Rejects every transaction on the accounts of some clients, e.g. those on a sanctions
+/// list. Transfers to them are rejected as well.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockedClients {
+    clients: HashSet<u16>,
+}
+
+impl BlockedClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_client(mut self, client: u16) -> Self {
+        self.clients.insert(client);
+        self
+    }
+
+    /// Loads the clients from a file with a client id per line. A `client` header line,
+    /// blank lines and lines starting with `#` are skipped.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        let rows: Vec<BlockedClientRow> = read_rows(reader, &["client"], "client")?;
+
+        Ok(rows
+            .into_iter()
+            .fold(Self::new(), |blocked, row| blocked.with_client(row.client)))
+    }
+}
+
+// A row of a file of blocked clients
+#[derive(Deserialize)]
+struct BlockedClientRow {
+    client: u16,
+}
+
+impl TransactionValidator for BlockedClients {
+    fn name(&self) -> &str {
+        "blocked_clients"
+    }
+
+    fn validate(&self, t: &Transaction, _ledger: &LedgerView) -> Result<(), String> {
+        match std::iter::once(t.client())
+            .chain(t.counterparty())
+            .find(|client| self.clients.contains(client))
+        {
+            Some(client) => Err(format!("client {client} is blocked")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Rejects deposits, withdrawals, transfers, fees, interest and exchanges of more than a
+/// given amount. Disputes and the like are left alone, the amount having been allowed
+/// once already
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaxAmount {
+    amount: f64,
+}
+
+impl MaxAmount {
+    pub fn new(amount: f64) -> Self {
+        Self { amount }
+    }
+}
+
+impl TransactionValidator for MaxAmount {
+    fn name(&self) -> &str {
+        "max_amount"
+    }
+
+    fn validate(&self, t: &Transaction, _ledger: &LedgerView) -> Result<(), String> {
+        if t.kind().refers_to_earlier() || t.kind().is_administrative() {
+            return Ok(());
+        }
+
+        match t.amount() {
+            Some(amount) if amount > self.amount => Err(format!(
+                "amount {amount} is over the maximum of {}",
+                self.amount
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{Deposit, Dispute, Transfer};
+
+    #[test]
+    fn test_blocked_clients_and_max_amount() {
+        let history = TransactionHistory::new();
+        let ledger = LedgerView {
+            balance: None,
+            history: &history,
+        };
+
+        let blocked =
+            BlockedClients::from_reader("client\n# sanctioned\n3\n\n7\n".as_bytes()).unwrap();
+        assert_eq!(blocked, BlockedClients::new().with_client(3).with_client(7));
+        assert!(BlockedClients::from_reader("three\n".as_bytes()).is_err());
+
+        let deposit = Transaction::Deposit(Deposit::new(1, 1, 50.0));
+        assert_eq!(blocked.validate(&deposit, &ledger), Ok(()));
+
+        let transfer = Transaction::Transfer(Transfer::new(1, 7, 2, 5.0));
+        assert_eq!(
+            blocked.validate(&transfer, &ledger),
+            Err("client 7 is blocked".to_string())
+        );
+
+        let max_amount = MaxAmount::new(20.0);
+        assert_eq!(max_amount.validate(&transfer, &ledger), Ok(()));
+        assert!(max_amount.validate(&deposit, &ledger).is_err());
+
+        let dispute = Transaction::Dispute(Dispute::new(1, 1).with_amount(30.0));
+        assert_eq!(max_amount.validate(&dispute, &ledger), Ok(()));
+    }
+}